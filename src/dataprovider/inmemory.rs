@@ -0,0 +1,24 @@
+use crate::ratings::{Id, RatingValue, RatingContainer, RatingContainerBuilder};
+use super::{RatingDataProvider, ProviderError};
+
+/// Serves a fixed list of (animeid, userid, rating) triples that is already in memory,
+/// e.g. the train part of a holdout split.
+pub struct InMemoryDataProvider {
+    data: Vec<(Id, Id, RatingValue)>
+}
+
+impl InMemoryDataProvider {
+    pub fn new(data: Vec<(Id, Id, RatingValue)>) -> Self {
+        return InMemoryDataProvider{ data };
+    }
+}
+
+impl RatingDataProvider for InMemoryDataProvider {
+    fn get(&self) -> Result<RatingContainer, ProviderError> {
+        let mut rating_builder = RatingContainerBuilder::new();
+        for &(animeid, userid, rating) in &self.data {
+            rating_builder.add_rating(animeid, userid, rating);
+        }
+        return Ok(rating_builder.build());
+    }
+}
@@ -3,13 +3,70 @@ extern crate mysql;
 
 mod testdata;
 mod sql;
-mod unittestdata;
-use crate::ratings::RatingContainer;
+mod inmemory;
+use std::{fmt, io, collections::HashMap};
+use crate::ratings::{RatingContainer, RatingValue};
 
 pub use self::testdata::TestDataCsvProvider;
 pub use self::sql::SQLDataProvider;
-pub use self::unittestdata::UnitTestDataProvider;
+pub use self::inmemory::InMemoryDataProvider;
+
+/// Ratings on the rating scale for interactions (e.g. "completed" or "dropped"),
+/// that a data source delivers instead of rating values.
+pub type Interactions = HashMap<String, RatingValue>;
+
+/// Error codes of the MySQL server, that only mean it is overloaded or restarting right now:
+/// Too many connections (1040, 1203, 1226), shutdown in progress (1053), lock wait timeout (1205),
+/// deadlock (1213), query interrupted or killed (1317, 1927), server gone away or lost (2006, 2013).
+const TEMPORARY_MYSQL_ERRORS: [u16; 10] = [1040, 1053, 1203, 1205, 1213, 1226, 1317, 1927, 2006, 2013];
+
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The data source could not be reached.
+    Connection(mysql::Error),
+    /// The data source was reached, but fetching the ratings from it failed.
+    Query(mysql::Error),
+    Io(io::Error),
+    /// The data source contains something, that is not a rating.
+    Format(String)
+}
+impl ProviderError {
+    /// Whether the data source is probably only down for now, so that trying again later makes sense.
+    pub fn is_temporary(&self) -> bool {
+        return match self {
+            ProviderError::Connection(e) | ProviderError::Query(e) => match e {
+                mysql::Error::IoError(_) => true,
+                // Other driver errors are protocol or setup errors, that won't go away by themselves
+                mysql::Error::DriverError(e) => match e {
+                    mysql::DriverError::ConnectTimeout | mysql::DriverError::CouldNotConnect(Some(_)) | mysql::DriverError::Timeout => true,
+                    _ => false
+                },
+                mysql::Error::MySqlError(e) => TEMPORARY_MYSQL_ERRORS.contains(&e.code),
+                _ => false
+            },
+            // A missing or unreadable file won't show up by itself
+            ProviderError::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => false,
+                _ => true
+            },
+            ProviderError::Format(_) => false
+        };
+    }
+}
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::Connection(e) => write!(f, "Failed to connect to the data source: {}", e),
+            ProviderError::Query(e) => write!(f, "Failed to fetch ratings from the data source: {}", e),
+            ProviderError::Io(e) => write!(f, "Failed to read ratings: {}", e),
+            ProviderError::Format(msg) => write!(f, "Invalid rating data: {}", msg)
+        }
+    }
+}
+impl From<io::Error> for ProviderError {
+    fn from(e: io::Error) -> Self { ProviderError::Io(e) }
+}
 
 pub trait RatingDataProvider {
-    fn get(&self) -> RatingContainer;
+    fn get(&self) -> Result<RatingContainer, ProviderError>;
 }
@@ -1,50 +1,59 @@
-use crate::ratings::{RatingContainer, RatingContainerBuilder, RatingValue};
-use super::RatingDataProvider;
+use crate::ratings::{RatingContainer, RatingContainerBuilder, RatingValue, RatingScale};
+use super::{RatingDataProvider, ProviderError, Interactions};
 
 use mysql as my;
 
 pub struct SQLDataProvider {
     connection_string: String,
-    query: String
+    query: String,
+    scale: RatingScale,
+    interactions: Interactions
 }
 impl SQLDataProvider {
-    pub fn new(connection_string: &str, where_clause: &str, aid_name: &str, uid_name: &str, rating_name: &str, table_name: &str) -> Self {
+    pub fn new(connection_string: &str, where_clause: &str, aid_name: &str, uid_name: &str, rating_name: &str, table_name: &str, scale: RatingScale, interactions: Interactions) -> Self {
         let where_clause_str = match where_clause.len() {
             0 => "".to_owned(),
             _ => format!("WHERE {}", where_clause)
         };
         return Self {
             connection_string: connection_string.to_owned(),
-            query: format!("SELECT {}, {}, {} FROM {} {}", aid_name, uid_name, rating_name, table_name, where_clause_str)
+            query: format!("SELECT {}, {}, {} FROM {} {}", aid_name, uid_name, rating_name, table_name, where_clause_str),
+            scale, interactions
         };
     }
 }
 
 impl RatingDataProvider for SQLDataProvider {
-    fn get(&self) -> RatingContainer {
+    fn get(&self) -> Result<RatingContainer, ProviderError> {
         let mut rating_builder = RatingContainerBuilder::new();
+        let mut skipped = 0;
 
-        let pool = match my::Pool::new(&self.connection_string) {
-            Ok(pool) => pool,
-            Err(err) => {
-                error!(target: "SQLDataProvider", "Error while trying to connect:\n{:?}", err);
-                panic!();
-            }
-        };
-        
-        match pool.prep_exec(&self.query, ()) {
-            Ok(result) => {
-                result.map(|row| row.unwrap()).for_each(|row| {
-                    let (animeid, userid, rating) : (u64, u64, u64) = my::from_row(row);
-                    rating_builder.add_rating(animeid, userid, rating as RatingValue / 2.0)
-                });
-            },
-            Err(err) => {
-                error!(target: "SQLDataProvider", "Error while trying fetch data:\n{:?}", err);
-                panic!();
+        let pool = my::Pool::new(&self.connection_string).map_err(ProviderError::Connection)?;
+        let result = pool.prep_exec(&self.query, ()).map_err(ProviderError::Query)?;
+        for row in result {
+            let row = row.map_err(ProviderError::Query)?;
+            let (animeid, userid, rating) : (u64, u64, my::Value) = my::from_row_opt(row)
+                    .map_err(|_| ProviderError::Format("animeid and userid have to be unsigned integers".to_owned()))?;
+            // Integer columns can not be read as float directly
+            let rating = match rating {
+                my::Value::Int(rating) => self.scale.from_raw(rating as RatingValue),
+                my::Value::UInt(rating) => self.scale.from_raw(rating as RatingValue),
+                // Interactions are mapped to ratings on the scale, not to raw values
+                my::Value::Bytes(ref interaction) if !self.interactions.is_empty() => {
+                    self.interactions.get(&*String::from_utf8_lossy(interaction)).cloned()
+                },
+                rating => self.scale.from_raw(my::from_value_opt::<RatingValue>(rating)
+                    .map_err(|e| ProviderError::Format(format!("{:?} is not a rating", e.0)))?)
+            };
+            match rating {
+                Some(rating) => rating_builder.add_rating(animeid, userid, rating),
+                None => skipped += 1
             }
         }
+        if skipped > 0 {
+            warn!(target: "SQLDataProvider", "Skipped {} ratings outside of the rating scale or with unknown interactions", skipped);
+        }
 
-        return rating_builder.build();
+        return Ok(rating_builder.build());
     }
-}
\ No newline at end of file
+}
@@ -1,50 +1,68 @@
-use crate::ratings::{Id, RatingValue, RatingContainer, RatingContainerBuilder};
-use super::RatingDataProvider;
+use crate::ratings::{Id, RatingValue, RatingScale, RatingContainer, RatingContainerBuilder};
+use super::{RatingDataProvider, ProviderError, Interactions};
 use csv;
 use std::path::Path;
-use std::{io, fs};
+use std::fs;
 
 pub struct TestDataCsvProvider {
-    path: String
+    path: String,
+    scale: RatingScale,
+    interactions: Interactions
 }
 
 impl TestDataCsvProvider {
-    pub fn new(path: &str) -> Self {
+    pub fn new(path: &str, scale: RatingScale, interactions: Interactions) -> Self {
         return Self {
-            path: path.to_owned()
+            path: path.to_owned(),
+            scale, interactions
         };
     }
 
-    fn parse_file(path: &Path, animeid: Id, ratings: &mut RatingContainerBuilder) -> Result<(), io::Error> {
+    /// Add the ratings of the given file to `ratings`. Returns the amount of ratings
+    /// that were skipped, because they are not on the rating scale or are unknown interactions.
+    fn parse_file(&self, path: &Path, animeid: Id, ratings: &mut RatingContainerBuilder) -> Result<usize, ProviderError> {
         let mut rdr = csv::ReaderBuilder::new()
                 .has_headers(false)
                 .from_reader(fs::File::open(path)?);
+        let mut skipped = 0;
+        let invalid = || ProviderError::Format(format!("{} contains a line that is not <userid>,<rating>", path.display()));
         for rating in rdr.records() {
-            let rating = rating?;
-            let userid = rating.get(0).unwrap().parse::<Id>().unwrap();
-            // Move rating value into the range 0 to 5
-            let rating = rating.get(1).unwrap().parse::<RatingValue>().unwrap() / 2.0;
-            ratings.add_rating(animeid, userid, rating)
+            let rating = rating.map_err(|e| ProviderError::Format(format!("{}: {}", path.display(), e)))?;
+            let userid = rating.get(0).and_then(|v| v.parse::<Id>().ok()).ok_or_else(invalid)?;
+            let value = rating.get(1).ok_or_else(invalid)?;
+            // Interactions are mapped to ratings on the scale, not to raw values
+            let rating = match (value.parse::<RatingValue>(), self.interactions.is_empty()) {
+                (Ok(raw), _) => self.scale.from_raw(raw),
+                (Err(_), false) => self.interactions.get(value).cloned(),
+                (Err(_), true) => return Err(invalid())
+            };
+            match rating {
+                Some(rating) => ratings.add_rating(animeid, userid, rating),
+                None => skipped += 1
+            }
         }
-        return Ok(());
+        return Ok(skipped);
     }
 }
 
 impl RatingDataProvider for TestDataCsvProvider {
-    fn get(&self) -> RatingContainer {
+    fn get(&self) -> Result<RatingContainer, ProviderError> {
         let mut rating_builder = RatingContainerBuilder::new();
+        let mut skipped = 0;
 
-        for file in fs::read_dir(&self.path).unwrap() {
-            if let Ok(file) = file {
-                let file_path = file.path();
-                let extension = file_path.extension().unwrap().to_str().unwrap_or("");
-                if file_path.is_file() && extension == "csv" {
-                    let anime_id = file_path.file_stem().unwrap().to_str().unwrap_or("").parse::<Id>().unwrap();
-                    let _ = Self::parse_file(&file.path(), anime_id, &mut rating_builder);
-                }
+        for file in fs::read_dir(&self.path)? {
+            let file_path = file?.path();
+            let extension = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            if file_path.is_file() && extension == "csv" {
+                let anime_id = file_path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<Id>().ok())
+                        .ok_or_else(|| ProviderError::Format(format!("{} is not named <animeid>.csv", file_path.display())))?;
+                skipped += self.parse_file(&file_path, anime_id, &mut rating_builder)?;
             }
         }
+        if skipped > 0 {
+            warn!(target: "TestDataCsvProvider", "Skipped {} ratings outside of the rating scale or with unknown interactions", skipped);
+        }
 
-        return rating_builder.build();
+        return Ok(rating_builder.build());
     }
 }
\ No newline at end of file
@@ -0,0 +1,244 @@
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+use serde_derive::Serialize;
+use std::{fmt, collections::{HashMap, HashSet}};
+use crate::ratings::{Id, RatingValue, RatingContainer};
+use crate::dataprovider::{InMemoryDataProvider, ProviderError};
+use crate::recommender::{RecommendationEngine, RecommendationEngineConf, TrainerKind, PredictionError};
+use crate::snapshot::EngineSnapshot;
+
+pub type RatingTriple = (Id, Id, RatingValue);
+
+/// Strategy used to divide the known ratings into a part the model
+/// is trained on, and a part that is held out to test it.
+pub enum HoldoutSplit {
+    /// Every rating ends up in the test set with a probability of `test_fraction`.
+    Random { test_fraction: f64, seed: u64 },
+    /// For every user that rated more than `n` animes, `n` randomly chosen ratings are held out.
+    LeaveNOut { n: usize, seed: u64 }
+}
+impl HoldoutSplit {
+    /// Split the given ratings into a (train, test) pair of (animeid, userid, rating) lists.
+    pub fn split(&self, data: &RatingContainer) -> (Vec<RatingTriple>, Vec<RatingTriple>) {
+        let all_ratings: Vec<RatingTriple> = data.ratings.iter().map(|r| (
+            data.row2anime(r.animeidx).unwrap(), data.column2user(r.useridx).unwrap(), r.rating
+        )).collect();
+        let (mut train, mut test) = (Vec::new(), Vec::new());
+
+        match *self {
+            HoldoutSplit::Random { test_fraction, seed } => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                for rating in all_ratings {
+                    if rng.gen::<f64>() < test_fraction { test.push(rating); } else { train.push(rating); }
+                }
+            },
+            HoldoutSplit::LeaveNOut { n, seed } => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut ratings_by_user: HashMap<Id, Vec<RatingTriple>> = HashMap::new();
+                for rating in all_ratings {
+                    ratings_by_user.entry(rating.1).or_insert_with(Vec::new).push(rating);
+                }
+                // Iterate users in a fixed order, so the split only depends on the seed
+                let mut userids: Vec<Id> = ratings_by_user.keys().map(|u| *u).collect();
+                userids.sort();
+                for userid in userids {
+                    let mut user_ratings = ratings_by_user.remove(&userid).unwrap();
+                    // Users need to keep at least one rating, otherwise they are unknown to the model
+                    if user_ratings.len() > n {
+                        user_ratings.shuffle(&mut rng);
+                        test.extend(user_ratings.drain(..n));
+                    }
+                    train.extend(user_ratings);
+                }
+            }
+        }
+        return (train, test);
+    }
+}
+
+
+/// Parameters of the ranking metrics computed by the evaluation.
+#[derive(Clone)]
+pub struct EvaluationParams {
+    /// Length of the recommendation list that the ranking metrics are calculated on.
+    pub k: usize,
+    /// Minimum held-out rating for an anime to count as relevant to a user.
+    pub relevance_threshold: RatingValue,
+    /// Trade-off between rating and diversity the top-k lists are re-ranked with (0 disables it).
+    pub diversity: RatingValue
+}
+
+
+#[derive(Serialize, Clone)]
+pub struct EvaluationReport {
+    /// Unix timestamp (seconds) of when the evaluation was run.
+    pub created_at: u64,
+    /// Training algorithm of the evaluated model.
+    pub trainer: TrainerKind,
+    pub train_ratings: usize,
+    pub test_ratings: usize,
+    /// Number of held-out ratings whose user and anime were known to the model.
+    pub predicted_ratings: usize,
+    /// Number of users that had relevant held-out animes and contributed to the ranking metrics.
+    pub ranked_users: usize,
+    pub k: usize,
+    /// Errors of the predicted ratings. `None` for models that predict ranking scores instead of ratings.
+    pub rmse: Option<RatingValue>,
+    pub mae: Option<RatingValue>,
+    pub precision_at_k: f64,
+    pub recall_at_k: f64,
+    pub ndcg_at_k: f64,
+    pub map_at_k: f64,
+    /// Average over the users of the probability that a relevant held-out anime is ranked
+    /// above an anime the user didn't interact with (area under the ROC curve).
+    pub auc: f64,
+    /// Average over the users of the average dissimilarity of all pairs of animes in their top-k list.
+    pub intra_list_diversity: f64,
+    /// Fraction of the catalog that appeared in at least one user's top-k list.
+    pub catalog_coverage: f64
+}
+impl EvaluationReport {
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).unwrap();
+    }
+}
+
+
+#[derive(Debug)]
+pub enum EvaluationError {
+    Training(ProviderError),
+    Prediction(PredictionError)
+}
+impl fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvaluationError::Training(e) => write!(f, "Training on the train split failed: {}", e),
+            EvaluationError::Prediction(e) => write!(f, "Prediction failed during evaluation: {}", e)
+        }
+    }
+}
+impl From<ProviderError> for EvaluationError {
+    fn from(e: ProviderError) -> Self { EvaluationError::Training(e) }
+}
+impl From<PredictionError> for EvaluationError {
+    fn from(e: PredictionError) -> Self { EvaluationError::Prediction(e) }
+}
+
+
+/// Train a model with the given configuration on the train part of `data`
+/// and measure how well it predicts the held-out part.
+pub fn evaluate(config: RecommendationEngineConf, data: &RatingContainer, split: &HoldoutSplit, params: &EvaluationParams) -> Result<EvaluationReport, EvaluationError> {
+    let (train, test) = split.split(data);
+    info!(target: "Evaluation", "Split ratings into {} training and {} test ratings", train.len(), test.len());
+    return evaluate_split(config, data.animes.len(), train, &test, params);
+}
+
+/// Train a model with the given configuration on an already split set of ratings
+/// and measure how well it predicts the `test` ratings. `catalog_size` is the total
+/// amount of animes, which is used to calculate the catalog coverage.
+pub fn evaluate_split(config: RecommendationEngineConf, catalog_size: usize, train: Vec<RatingTriple>, test: &[RatingTriple], params: &EvaluationParams) -> Result<EvaluationReport, EvaluationError> {
+    let mut test_by_user: HashMap<Id, Vec<(Id, RatingValue)>> = HashMap::new();
+    for &(animeid, userid, rating) in test {
+        test_by_user.entry(userid).or_insert_with(Vec::new).push((animeid, rating));
+    }
+
+    let (trainer, train_ratings) = (config.trainer, train.len());
+    let engine = RecommendationEngine::new(config, Box::new(InMemoryDataProvider::new(train)));
+    engine.retrain()?;
+
+    let (mut squared_error, mut absolute_error, mut predicted_ratings) = (0.0, 0.0, 0);
+    let (mut precision, mut recall, mut ndcg, mut map, mut ranked_users) = (0.0, 0.0, 0.0, 0.0, 0);
+    let (mut auc, mut auc_users) = (0.0, 0);
+    let (mut intra_list_diversity, mut diversity_users) = (0.0, 0);
+    let mut recommended_animes: HashSet<Id> = HashSet::new();
+
+    for (userid, held_out) in &test_by_user {
+        // What the user rated in the train set is excluded from the ranking
+        let predictions = match engine.predict_user_ratings(*userid, false, 0, None, |_| true) {
+            Ok(predictions) => predictions,
+            Err(PredictionError::UnknownUser) => continue,
+            Err(e) => return Err(e.into())
+        };
+
+        // Rating prediction accuracy
+        let predicted: HashMap<Id, RatingValue> = predictions.iter().map(|p| (p.animeid, p.rating)).collect();
+        for &(animeid, rating) in held_out {
+            if let Some(prediction) = predicted.get(&animeid) {
+                squared_error += (rating - prediction).powi(2);
+                absolute_error += (rating - prediction).abs();
+                predicted_ratings += 1;
+            }
+        }
+
+        // Ranking quality of the top-k list
+        let top_k: Vec<Id> = if params.diversity > 0.0 {
+            let candidates = predictions.iter().take(params.k * RecommendationEngine::DIVERSITY_CANDIDATE_FACTOR).cloned().collect();
+            let diversified = engine.diversify(candidates, params.diversity, params.k)?;
+            diversified.iter().map(|p| p.animeid).collect()
+        } else {
+            predictions.iter().take(params.k).map(|p| p.animeid).collect()
+        };
+        recommended_animes.extend(top_k.iter());
+        if let Some(diversity) = engine.intra_list_diversity(&top_k)? {
+            intra_list_diversity += diversity;
+            diversity_users += 1;
+        }
+        let relevant: HashSet<Id> = held_out.iter()
+                .filter(|(_, rating)| *rating >= params.relevance_threshold)
+                .map(|(animeid, _)| *animeid).collect();
+        if relevant.is_empty() || top_k.is_empty() { continue; }
+
+        // Every anime that is neither held out nor rated in the train set is a negative. For each,
+        // count the relevant animes that are ranked above it, walking the list from the top.
+        let (mut relevant_above, mut correct_pairs, mut negatives) = (0, 0, 0);
+        for p in &predictions {
+            if relevant.contains(&p.animeid) {
+                relevant_above += 1;
+            } else if !held_out.iter().any(|&(animeid, _)| animeid == p.animeid) {
+                correct_pairs += relevant_above;
+                negatives += 1;
+            }
+        }
+        if relevant_above > 0 && negatives > 0 {
+            auc += correct_pairs as f64 / (relevant_above * negatives) as f64;
+            auc_users += 1;
+        }
+
+        let (mut hits, mut dcg, mut precision_sum) = (0, 0.0, 0.0);
+        for (rank, animeid) in top_k.iter().enumerate() {
+            if relevant.contains(animeid) {
+                hits += 1;
+                dcg += 1.0 / ((rank + 2) as f64).log2();
+                precision_sum += hits as f64 / (rank + 1) as f64;
+            }
+        }
+        let ideal_hits = usize::min(relevant.len(), params.k);
+        let idcg: f64 = (0..ideal_hits).map(|rank| 1.0 / ((rank + 2) as f64).log2()).sum();
+
+        precision += hits as f64 / params.k as f64;
+        recall += hits as f64 / relevant.len() as f64;
+        ndcg += dcg / idcg;
+        map += precision_sum / ideal_hits as f64;
+        ranked_users += 1;
+    }
+
+    let per_rating = |sum: f64| if predicted_ratings > 0 { sum / predicted_ratings as f64 } else { 0.0 };
+    let per_user = |sum: f64| if ranked_users > 0 { sum / ranked_users as f64 } else { 0.0 };
+    return Ok(EvaluationReport {
+        created_at: EngineSnapshot::now(),
+        trainer,
+        train_ratings,
+        test_ratings: test.len(),
+        predicted_ratings,
+        ranked_users,
+        k: params.k,
+        rmse: if trainer.predicts_ratings() { Some(per_rating(squared_error).sqrt()) } else { None },
+        mae: if trainer.predicts_ratings() { Some(per_rating(absolute_error)) } else { None },
+        precision_at_k: per_user(precision),
+        recall_at_k: per_user(recall),
+        ndcg_at_k: per_user(ndcg),
+        map_at_k: per_user(map),
+        auc: if auc_users > 0 { auc / auc_users as f64 } else { 0.0 },
+        intra_list_diversity: if diversity_users > 0 { intra_list_diversity / diversity_users as f64 } else { 0.0 },
+        catalog_coverage: if catalog_size > 0 { recommended_animes.len() as f64 / catalog_size as f64 } else { 0.0 }
+    });
+}
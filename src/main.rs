@@ -5,6 +5,7 @@ const VERSION_MINOR: u32 = 1;
 
 extern crate kdtree;
 extern crate config;
+extern crate chrono;
 extern crate nalgebra;
 extern crate simplelog;
 extern crate serde_derive;
@@ -16,17 +17,28 @@ mod ratings;
 mod settings;
 mod dataprovider;
 mod recommender;
+mod snapshot;
+mod evaluation;
+mod search;
+mod scheduler;
 
-use std::thread;
+use std::{thread, fs, time::Duration};
+use std::path::Path;
 use std::sync::Arc;
 use rocket::{State, http::Status};
-use rocket_contrib::json::JsonValue;
+use rocket_contrib::json::{Json, JsonValue};
+use serde_derive::Deserialize;
 use simplelog::{TermLogger, TerminalMode, LevelFilter, Level};
 use crate::{
-    ratings::RatingValue,
+    ratings::{Id, RatingValue, RatingScale},
     dataprovider::*,
-    settings::RecoChanSettingsDataProvider,
-    recommender::{RecommendationEngine, PredictionError}
+    settings::{RecoChanSettings, RecoChanSettingsDataProvider, RecoChanSettingsRetry, RecoChanSettingsSchedule, RecoChanSettingsTrainer, RecoChanSettingsNeighborhoodSimilarity, RecoChanSettingsInitialization, RecoChanSettingsSanitizer, RecoChanSettingsTraining, RecoChanSettingsEvaluation, RecoChanSettingsSplit},
+    settings::{RecoChanSettingsSimilarityMetric, RecoChanSettingsNeighborIndex, RecoChanSettingsSearch, RecoChanSettingsSearchStrategy, RecoChanSettingsSearchMetric, RecoChanSettingsFallbackStrategy},
+    recommender::{RecommendationEngine, RecommendationEngineConf, RecommendationResult, TrainerKind, NeighborhoodSimilarity, FallbackStrategy, FeatureInitialization, SimilarityMetric, NeighborIndexKind, PredictionError, PREDICTION_SANITIZER_NOOP, PREDICTION_SANITIZER_CLAMP},
+    evaluation::{HoldoutSplit, EvaluationParams},
+    search::{SearchSpace, SearchStrategy, SearchMetric},
+    snapshot::EngineSnapshot,
+    scheduler::{Scheduler, Schedule}
 };
 
 // Change log-level depending on build-type for now
@@ -51,6 +63,235 @@ fn print_hello() {
     println!();
 }
 
+fn init_logging() {
+    let mut log_config = simplelog::Config::default();
+    log_config.target = Some(Level::Info);
+    TermLogger::init(LOGLEVEL, log_config, TerminalMode::Mixed).unwrap();
+}
+
+fn rating_scale(settings: &RecoChanSettings) -> RatingScale {
+    return settings.rating_scale.scale();
+}
+
+fn interactions(settings: &RecoChanSettings) -> Interactions {
+    return settings.dataprovider.interactions().iter().map(|i| (i.value.clone(), i.rating)).collect();
+}
+
+fn engine_conf(settings: &RecoChanSettings) -> RecommendationEngineConf {
+    let training = &settings.training;
+    return RecommendationEngineConf {
+        trainer: match training.trainer {
+            RecoChanSettingsTrainer::FunkSvd => TrainerKind::FunkSvd,
+            RecoChanSettingsTrainer::Als => TrainerKind::Als,
+            RecoChanSettingsTrainer::BiasedSgd => TrainerKind::BiasedSgd,
+            RecoChanSettingsTrainer::SvdPlusPlus => TrainerKind::SvdPlusPlus,
+            RecoChanSettingsTrainer::ItemKnn => TrainerKind::ItemKnn,
+            RecoChanSettingsTrainer::Bpr => TrainerKind::Bpr
+        },
+        features: training.features,
+        feature_labels: training.feature_labels.clone(),
+        learn_rate: training.learn_rate,
+        threads: training.threads,
+        min_steps: training.min_steps,
+        max_steps: training.max_steps,
+        min_improvement: training.min_improvement,
+        validation_fraction: training.validation_fraction,
+        regularization_parameter: training.regularization_parameter,
+        bias_regularization_parameter: training.bias_regularization_parameter,
+        prediction_sanitizer: match training.sanitizer {
+            RecoChanSettingsSanitizer::Noop => PREDICTION_SANITIZER_NOOP,
+            RecoChanSettingsSanitizer::Clamp => PREDICTION_SANITIZER_CLAMP
+        },
+        rating_scale: rating_scale(settings),
+        round_predictions: settings.rating_scale.round_predictions,
+        similarity: match settings.similarity.metric {
+            RecoChanSettingsSimilarityMetric::Euclidean => SimilarityMetric::Euclidean,
+            RecoChanSettingsSimilarityMetric::Cosine => SimilarityMetric::Cosine,
+            RecoChanSettingsSimilarityMetric::DotProduct => SimilarityMetric::DotProduct
+        },
+        neighbor_index: match settings.similarity.index {
+            RecoChanSettingsNeighborIndex::KdTree => NeighborIndexKind::KdTree,
+            RecoChanSettingsNeighborIndex::BruteForce => NeighborIndexKind::BruteForce,
+            RecoChanSettingsNeighborIndex::Hnsw { m, ef_construction, ef_search } => NeighborIndexKind::Hnsw { m, ef_construction, ef_search }
+        },
+        initial_approximation_value: training.initial_approximation_value,
+        initialization: match training.initialization {
+            RecoChanSettingsInitialization::Constant => FeatureInitialization::Constant,
+            RecoChanSettingsInitialization::Uniform => FeatureInitialization::Uniform,
+            RecoChanSettingsInitialization::Normal => FeatureInitialization::Normal
+        },
+        initialization_scale: training.initialization_scale,
+        shuffle: training.shuffle,
+        seed: training.seed,
+        online_steps: training.online_steps,
+        online_update_animes: training.online_update_animes,
+        positive_threshold: training.positive_threshold,
+        neighborhood_similarity: match training.neighborhood_similarity {
+            RecoChanSettingsNeighborhoodSimilarity::AdjustedCosine => NeighborhoodSimilarity::AdjustedCosine,
+            RecoChanSettingsNeighborhoodSimilarity::Pearson => NeighborhoodSimilarity::Pearson
+        },
+        neighborhood_size: training.neighborhood_size,
+        neighborhood_shrinkage: training.neighborhood_shrinkage,
+        fallback: settings.fallback.strategies.iter().map(|strategy| match strategy {
+            RecoChanSettingsFallbackStrategy::Popular => FallbackStrategy::Popular,
+            RecoChanSettingsFallbackStrategy::Trending { window_secs } => FallbackStrategy::Trending { window_secs: *window_secs },
+            RecoChanSettingsFallbackStrategy::Curated { animes } => FallbackStrategy::Curated { animes: animes.clone() }
+        }).collect(),
+        fallback_count: settings.fallback.count,
+        k: training.k
+    };
+}
+
+/// Train a model on a holdout split of the configured data and report
+/// how well it predicts the held-out ratings, instead of starting the API.
+fn run_evaluation(engine_conf: RecommendationEngineConf, eval_settings: &RecoChanSettingsEvaluation, dataprovider: Box<dyn RatingDataProvider + Send + Sync>, report_path: Option<&String>) {
+    let (split, params) = evaluation_setup(eval_settings);
+
+    info!(target: "Reco-Chan", "You want to test me?! Fine, I'll show you how good I am!");
+    let ratings = match dataprovider.get() {
+        Ok(ratings) => ratings,
+        Err(e) => {
+            error!(target: "Reco-Chan", "How am I supposed to show off without any ratings?! {}", e);
+            return;
+        }
+    };
+    let report = match evaluation::evaluate(engine_conf, &ratings, &split, &params) {
+        Ok(report) => report,
+        Err(e) => {
+            error!(target: "Reco-Chan", "Th-that didn't count! Something went wrong: {}", e);
+            return;
+        }
+    };
+    let report_json = report.to_json();
+    match report_path {
+        Some(path) => {
+            fs::write(path, &report_json).expect("Failed to write evaluation report");
+            info!(target: "Reco-Chan", "I wrote my results to {}. Not that I care whether you read them.", path);
+        },
+        None => println!("{}", report_json)
+    }
+}
+
+/// Train and evaluate many configurations on the same holdout split and
+/// report them ranked by the configured metric, instead of starting the API.
+fn run_search(engine_conf: RecommendationEngineConf, training_settings: &RecoChanSettingsTraining, eval_settings: &RecoChanSettingsEvaluation, search_settings: &RecoChanSettingsSearch,
+                dataprovider: Box<dyn RatingDataProvider + Send + Sync>, results_path: Option<&String>) {
+    let (split, params) = evaluation_setup(eval_settings);
+    let space = SearchSpace {
+        features: search_settings.features.clone(),
+        learn_rate: search_settings.learn_rate.clone(),
+        regularization_parameter: search_settings.regularization_parameter.clone(),
+        k: search_settings.k.clone()
+    };
+    let strategy = match search_settings.strategy {
+        RecoChanSettingsSearchStrategy::Grid => SearchStrategy::Grid,
+        RecoChanSettingsSearchStrategy::Random { samples, seed } => SearchStrategy::Random { samples, seed }
+    };
+    let metric = match search_settings.rank_by {
+        RecoChanSettingsSearchMetric::Rmse => SearchMetric::Rmse,
+        RecoChanSettingsSearchMetric::Mae => SearchMetric::Mae,
+        RecoChanSettingsSearchMetric::NdcgAtK => SearchMetric::NdcgAtK,
+        RecoChanSettingsSearchMetric::MapAtK => SearchMetric::MapAtK,
+        RecoChanSettingsSearchMetric::RecallAtK => SearchMetric::RecallAtK,
+        RecoChanSettingsSearchMetric::Auc => SearchMetric::Auc
+    };
+
+    let ratings = match dataprovider.get() {
+        Ok(ratings) => ratings,
+        Err(e) => {
+            error!(target: "Reco-Chan", "How am I supposed to try anything without any ratings?! {}", e);
+            return;
+        }
+    };
+
+    info!(target: "Reco-Chan", "So many configurations... I'll try them all, but don't think I'm enjoying this!");
+    let results = match search::search(&engine_conf, &ratings, &split, &params,
+                                       space.candidates(&strategy), metric, search_settings.threads) {
+        Ok(results) => results,
+        Err(e) => {
+            error!(target: "Reco-Chan", "I-I'm not giving up because I can't do it! Something went wrong: {}", e);
+            return;
+        }
+    };
+    println!("{}", search::format_table(&results));
+    let best_snippet = match results.first() {
+        Some(best) => search::training_snippet(training_settings, best),
+        None => {
+            warn!(target: "Reco-Chan", "There was nothing to try. Did you even configure a search space?");
+            return;
+        }
+    };
+    println!("Best configuration:\n{}", serde_json::to_string_pretty(&best_snippet).unwrap());
+
+    if let Some(path) = results_path {
+        let results_json = serde_json::json!({ "results": results, "best": best_snippet });
+        fs::write(path, serde_json::to_string_pretty(&results_json).unwrap()).expect("Failed to write search results");
+        info!(target: "Reco-Chan", "I wrote the ranking to {}. Be grateful!", path);
+    }
+}
+
+/// Train a model (or load its snapshot) and compare how fast and exact the different
+/// neighbor indices find similar users / animes on it, instead of starting the API.
+fn run_index_benchmark(engine_conf: RecommendationEngineConf, snapshot_path: &str, dataprovider: Box<dyn RatingDataProvider + Send + Sync>, results_path: Option<&String>) {
+    const BENCHMARK_K: usize = 10;
+    const BENCHMARK_QUERIES: usize = 1000;
+    let mut kinds = vec![NeighborIndexKind::KdTree, NeighborIndexKind::BruteForce];
+    kinds.push(match engine_conf.neighbor_index {
+        NeighborIndexKind::Hnsw { .. } => engine_conf.neighbor_index,
+        _ => NeighborIndexKind::Hnsw { m: 16, ef_construction: 200, ef_search: 64 }
+    });
+
+    let recom_engine = RecommendationEngine::new(engine_conf, dataprovider);
+    if snapshot_path.is_empty() || recom_engine.load_snapshot(Path::new(snapshot_path)).is_err() {
+        info!(target: "Reco-Chan", "I have to study first, before I can show off how fast I am!");
+        if let Err(e) = recom_engine.retrain() {
+            error!(target: "Reco-Chan", "How am I supposed to study without any ratings?! {}", e);
+            return;
+        }
+    }
+    info!(target: "Reco-Chan", "Searching neighbors the slow way too, just so you can see how fast I usually am.");
+    let results = recom_engine.benchmark_neighbor_indices(&kinds, BENCHMARK_K, BENCHMARK_QUERIES).unwrap();
+    println!("{}", recommender::format_benchmark_table(&results));
+    if let Some(path) = results_path {
+        fs::write(path, serde_json::to_string_pretty(&results).unwrap()).expect("Failed to write benchmark results");
+        info!(target: "Reco-Chan", "I wrote the results to {}. Be grateful!", path);
+    }
+}
+
+/// Retrain the engine. While the dataprovider is only temporarily unavailable, this is retried up to
+/// `retry.max_attempts` times, with exponentially growing pauses in between. Until then, the current
+/// model keeps being served. Returns whether the engine was retrained.
+fn retrain_with_backoff(recom_engine: &RecommendationEngine, retry: &RecoChanSettingsRetry) -> bool {
+    let mut backoff_sec = retry.initial_backoff_sec;
+    for attempt in 1..=retry.max_attempts {
+        match recom_engine.retrain() {
+            Ok(()) => return true,
+            Err(e) if e.is_temporary() && attempt < retry.max_attempts => {
+                warn!(target: "Reco-Chan", "I couldn't get the ratings: {}. I'll try again in {}sec, so you better fix this!", e, backoff_sec);
+                thread::sleep(Duration::from_secs(backoff_sec));
+                backoff_sec = backoff_sec.saturating_mul(2).min(retry.max_backoff_sec);
+            },
+            Err(e) if e.is_temporary() => {
+                error!(target: "Reco-Chan", "I couldn't get the ratings {} times in a row: {}. I'm not wasting any more time until the next scheduled training!", attempt, e);
+            },
+            Err(e) => {
+                error!(target: "Reco-Chan", "I couldn't get the ratings: {}. Trying again won't help, so I'll stick to what I know.", e);
+                return false;
+            }
+        }
+    }
+    return false;
+}
+
+fn evaluation_setup(eval_settings: &RecoChanSettingsEvaluation) -> (HoldoutSplit, EvaluationParams) {
+    let split = match eval_settings.split {
+        RecoChanSettingsSplit::Random { test_fraction, seed } => HoldoutSplit::Random { test_fraction, seed },
+        RecoChanSettingsSplit::LeaveNOut { n, seed } => HoldoutSplit::LeaveNOut { n, seed }
+    };
+    let params = EvaluationParams { k: eval_settings.k, relevance_threshold: eval_settings.relevance_threshold, diversity: eval_settings.diversity };
+    return (split, params);
+}
+
 fn main() {
     // Load and parse configuration file
     let settings = match settings::RecoChanSettings::open("recochan.json") {
@@ -62,40 +303,123 @@ fn main() {
     };
 
     // Instantiate configured dataprovider
-    let dataprovider: Box<dyn RatingDataProvider + Send + Sync> = match settings.dataprovider {
-        RecoChanSettingsDataProvider::SQL { connection_string, where_clause, aid_name, uid_name, rating_name, table_name } => {
-            Box::new(SQLDataProvider::new(&connection_string, &where_clause, &aid_name, &uid_name, &rating_name, &table_name))
+    let dataprovider: Box<dyn RatingDataProvider + Send + Sync> = match &settings.dataprovider {
+        RecoChanSettingsDataProvider::SQL { connection_string, where_clause, aid_name, uid_name, rating_name, table_name, .. } => {
+            Box::new(SQLDataProvider::new(connection_string, where_clause, aid_name, uid_name, rating_name, table_name, rating_scale(&settings), interactions(&settings)))
         }
-        RecoChanSettingsDataProvider::TestCSV { path } => Box::new(TestDataCsvProvider::new(&path))
+        RecoChanSettingsDataProvider::TestCSV { path, .. } => Box::new(TestDataCsvProvider::new(path, rating_scale(&settings), interactions(&settings)))
     };
 
     info!(target: "Reco-Chan", "I'm applying the configuration you gave me, but only because I got nothing else to do!");
 
+    // Offline modes: recochan evaluate [report.json] / recochan search [results.json] / recochan benchmark [results.json]
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("evaluate") => {
+            init_logging();
+            run_evaluation(engine_conf(&settings), &settings.evaluation, dataprovider, args.get(2));
+            return;
+        },
+        Some("search") => {
+            init_logging();
+            run_search(engine_conf(&settings), &settings.training, &settings.evaluation, &settings.search, dataprovider, args.get(2));
+            return;
+        },
+        Some("benchmark") => {
+            init_logging();
+            run_index_benchmark(engine_conf(&settings), &settings.snapshot.path, dataprovider, args.get(2));
+            return;
+        },
+        _ => {}
+    }
+
     // Create recommendation engine using configured dataprovider
-    let recom_engine = Arc::new(RecommendationEngine::new_default(dataprovider));
+    let mut recom_engine = RecommendationEngine::new(engine_conf(&settings), dataprovider);
+    if settings.snapshot.path.len() > 0 {
+        recom_engine = recom_engine.with_snapshot_path(&settings.snapshot.path);
+    }
+    if settings.training.curve_path.len() > 0 {
+        recom_engine = recom_engine.with_training_curve_path(&settings.training.curve_path);
+    }
+    let recom_engine = Arc::new(recom_engine);
 
     print_hello();
     // Initialize logging
-    let mut log_config = simplelog::Config::default();
-    log_config.target = Some(Level::Info);
-    TermLogger::init(LOGLEVEL, log_config, TerminalMode::Mixed).unwrap();
-    info!(target: "Reco-Chan", "Executing initial training round...");
-    info!(target: "Reco-Chan", "I'm not doing this for you though, I'm doing this because I want to! (,,Ծ‸Ծ,, )");
+    init_logging();
 
-    // Train initial round before starting web-server
-    recom_engine.retrain();
+    let schedule = match &settings.schedule {
+        RecoChanSettingsSchedule::Interval => Schedule::Interval { every_secs: settings.retrain_every_sec },
+        RecoChanSettingsSchedule::Cron { expression } => Schedule::Cron(expression.parse().expect("Cron expression was validated with the settings"))
+    };
+
+    // Try to restore the model from the last run, so we don't have to wait for a full training round.
+    // A snapshot is fresh, as long as no scheduled retrain was missed since it was written.
+    let now = Scheduler::now();
+    let (mut last_training, mut initial_training, mut retrain_in_background) = (now, true, false);
+    // A stale snapshot that isn't served never reaches the engine, so a failed initial training can't serve it after all
+    if settings.snapshot.path.len() > 0 {
+        match EngineSnapshot::load(Path::new(&settings.snapshot.path)) {
+            Ok(snapshot) => {
+                let age = snapshot.age();
+                let fresh = schedule.next_after(now.saturating_sub(age)).map_or(true, |next_run| next_run > now);
+                if !fresh && !settings.snapshot.serve_stale {
+                    info!(target: "Reco-Chan", "What I remember is {}sec old. I refuse to tell you anything before I studied again!", age);
+                } else if let Err(e) = recom_engine.restore_snapshot(snapshot) {
+                    warn!(target: "Reco-Chan", "I couldn't remember anything from last time: {}", e);
+                } else {
+                    if fresh {
+                        info!(target: "Reco-Chan", "I still remember what I learned {}sec ago. Don't expect me to study again just because you restarted me!", age);
+                    } else {
+                        // The missed retrain is due right away once the scheduler starts
+                        info!(target: "Reco-Chan", "What I remember is {}sec old. You can have it for now, while I'm studying again in the background.", age);
+                    }
+                    initial_training = false;
+                    last_training = now.saturating_sub(age);
+                }
+            },
+            Err(e) => {
+                warn!(target: "Reco-Chan", "I couldn't remember anything from last time: {}", e);
+            }
+        }
+    }
+
+    if initial_training {
+        info!(target: "Reco-Chan", "Executing initial training round...");
+        info!(target: "Reco-Chan", "I'm not doing this for you though, I'm doing this because I want to! (,,Ծ‸Ծ,, )");
 
-    info!(target: "Reco-Chan", "Initial training has finished. If you ask me for recommendations now, I MAY tell you the answer. But only reluctantly! ヽ(*≧ω≦)ﾉ");
+        // Train initial round before starting web-server
+        match recom_engine.retrain() {
+            Ok(()) => {
+                info!(target: "Reco-Chan", "Initial training has finished. If you ask me for recommendations now, I MAY tell you the answer. But only reluctantly! ヽ(*≧ω≦)ﾉ");
+            },
+            Err(e) => {
+                // Don't crash, the data source may come back while the API is already up
+                error!(target: "Reco-Chan", "I couldn't study, because I couldn't get the ratings: {}. I'll keep trying in the background.", e);
+                retrain_in_background = true;
+            }
+        }
+        last_training = Scheduler::now();
+    }
 
-    // Start thread that will do the periodical re-training
-    let (recom_engine_clone, retrain_every_sec) = (recom_engine.clone(), settings.retrain_every_sec);
-    info!(target: "Reco-Chan", "Maybe I will remember to retrain every {}sec. But I will probably forget.", retrain_every_sec);
-    thread::spawn(move || {
-        let recom_engine = &recom_engine_clone;
-        thread::sleep(std::time::Duration::from_secs(retrain_every_sec));
+    // Start the scheduler that will do the periodical re-training
+    match &settings.schedule {
+        RecoChanSettingsSchedule::Interval => info!(target: "Reco-Chan", "Maybe I will remember to retrain every {}sec. But I will probably forget.", settings.retrain_every_sec),
+        RecoChanSettingsSchedule::Cron { expression } => info!(target: "Reco-Chan", "Maybe I will remember to retrain at '{}' (UTC). But I will probably forget.", expression)
+    }
+    let recom_engine_clone = recom_engine.clone();
+    let retry = settings.retry;
+    let scheduler = Arc::new(Scheduler::new(schedule, Box::new(move || {
         info!(target: "Reco-Chan", "Ugh, I can't believe I actually remembered that you asked me to retrain now.");
-        recom_engine.retrain();
-    });
+        let retrained = retrain_with_backoff(&recom_engine_clone, &retry);
+        if retrained {
+            info!(target: "Reco-Chan", "I'm done studying. Now my answers are fresh again, not that you deserve them.");
+        }
+        return retrained;
+    })));
+    scheduler.start(last_training);
+    if retrain_in_background {
+        scheduler.run_now();
+    }
 
     // Configure and startup Web-API
     let api_env = if cfg!(debug_assertions) { rocket::config::Environment::Development } else { rocket::config::Environment::Production };
@@ -107,26 +431,101 @@ fn main() {
 
     rocket::custom(api_config)
             .manage(recom_engine)
+            .manage(scheduler)
             .mount("/", routes![
                 endpoint_personal_recommendation,
+                endpoint_foldin_recommendation,
+                endpoint_ingest_rating,
                 endpoint_similar_users,
-                endpoint_similar_animes
+                endpoint_similar_animes,
+                endpoint_features,
+                endpoint_user_features,
+                endpoint_training_curve,
+                endpoint_training_schedule,
+                endpoint_pause_training_schedule,
+                endpoint_resume_training_schedule,
+                endpoint_run_training
             ])
             .launch();
 }
 
 
-#[get("/users/<userid>/recommend?<minrating>")]
-fn endpoint_personal_recommendation(userid: u64, recom_engine: State<Arc<RecommendationEngine>>, minrating: Option<RatingValue>) -> Result<JsonValue, Status> {
-    let effective_min_rating = minrating.unwrap_or(-1.0);
-    match recom_engine.predict_user_ratings(userid, |p| p.rating >= effective_min_rating) {
-        Ok(prediction) => {
-            return Ok(json!(prediction));
+#[get("/users/<userid>/recommend?<minrating>&<include_rated>&<count>&<offset>&<diversity>&<explain>")]
+fn endpoint_personal_recommendation(userid: u64, recom_engine: State<Arc<RecommendationEngine>>, minrating: Option<RatingValue>, include_rated: Option<bool>,
+                                    count: Option<usize>, offset: Option<usize>, diversity: Option<RatingValue>, explain: Option<bool>) -> Result<JsonValue, Status> {
+    let effective_min_rating = minrating.unwrap_or(std::f64::NEG_INFINITY);
+    let (include_rated, explain, offset) = (include_rated.unwrap_or(false), explain.unwrap_or(false), offset.unwrap_or(0));
+    if explain && count.is_none() {
+        return Err(Status::new(400, "I'm not explaining the whole catalog to you! Tell me a count!"));
+    }
+    let result = match (diversity, count) {
+        (None, _) => recom_engine.predict_user_ratings(userid, include_rated, offset, count, |p| p.rating >= effective_min_rating),
+        (Some(diversity), Some(count)) if diversity >= 0.0 && diversity <= 1.0 => {
+            let candidates = offset.saturating_add(count).saturating_mul(RecommendationEngine::DIVERSITY_CANDIDATE_FACTOR);
+            recom_engine.predict_user_ratings(userid, include_rated, 0, Some(candidates), |p| p.rating >= effective_min_rating)
+                .and_then(|predictions| recom_engine.diversify(predictions, diversity, offset.saturating_add(count)))
+                .map(|diversified| diversified.into_iter().skip(offset).collect())
+        },
+        (Some(_), Some(_)) => return Err(Status::new(400, "Diversity goes from 0 to 1, baka!")),
+        (Some(_), None) => return Err(Status::new(400, "Diverse out of how many? Tell me a count!"))
+    };
+    // Only the final page is explained
+    let result = match (result, explain) {
+        (Ok(mut predictions), true) => recom_engine.explain_predictions(userid, &mut predictions).map(|_| predictions),
+        (result, _) => result
+    };
+    match result {
+        Ok(predictions) => {
+            return Ok(json!(RecommendationResult::personal(predictions)));
         },
         Err(e) => {
             match e {
-                PredictionError::UnknownUser => {
-                    return Err(Status::new(404, "I can not yet predict something for this user, sorry!"));
+                PredictionError::UnknownUser | PredictionError::NotInitialized => {
+                    // Better than nothing... but don't get used to it!
+                    match recom_engine.fallback_recommendations() {
+                        Ok(Some(fallback)) => return Ok(json!(fallback)),
+                        Ok(None) => {},
+                        Err(_) => return Err(Status::new(500, "Some weird mistake occured, sorry!"))
+                    }
+                    return match e {
+                        PredictionError::UnknownUser => Err(Status::new(404, "I can not yet predict something for this user, sorry!")),
+                        _ => Err(Status::new(503, "I haven't studied anything yet!"))
+                    };
+                },
+                _ => {
+                    return Err(Status::new(500, "Some weird mistake occured, sorry!"));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PostedRating {
+    animeid: Id,
+    rating: RatingValue
+}
+
+#[post("/users/foldin?<minrating>&<count>", format = "json", data = "<ratings>")]
+fn endpoint_foldin_recommendation(ratings: Json<Vec<PostedRating>>, minrating: Option<RatingValue>, count: Option<usize>, recom_engine: State<Arc<RecommendationEngine>>) -> Result<JsonValue, Status> {
+    let effective_min_rating = minrating.unwrap_or(std::f64::NEG_INFINITY);
+    let ratings: Option<Vec<(Id, RatingValue)>> = ratings.iter()
+            .map(|r| recom_engine.rating_scale().snap(r.rating).map(|value| (r.animeid, value))).collect();
+    let ratings = match ratings {
+        Some(ratings) => ratings,
+        None => return Err(Status::new(400, "Some of these are not ratings, baka!"))
+    };
+    match recom_engine.fold_in_user(&ratings, |p| p.rating >= effective_min_rating, count.unwrap_or(5)) {
+        Ok(result) => {
+            return Ok(json!(result));
+        },
+        Err(e) => {
+            match e {
+                PredictionError::UnknownAnime => {
+                    return Err(Status::new(404, "I don't know any of these animes, so how am I supposed to know what you like?!"));
+                },
+                PredictionError::NotInitialized => {
+                    return Err(Status::new(503, "I haven't studied anything yet!"));
                 },
                 _ => {
                     return Err(Status::new(500, "Some weird mistake occured, sorry!"));
@@ -136,6 +535,22 @@ fn endpoint_personal_recommendation(userid: u64, recom_engine: State<Arc<Recomme
     }
 }
 
+#[post("/users/<userid>/ratings", format = "json", data = "<rating>")]
+fn endpoint_ingest_rating(userid: u64, rating: Json<PostedRating>, recom_engine: State<Arc<RecommendationEngine>>) -> Result<JsonValue, Status> {
+    let value = match recom_engine.rating_scale().snap(rating.rating) {
+        Some(value) => value,
+        None => return Err(Status::new(400, "That's not a rating, baka!"))
+    };
+    match recom_engine.ingest_rating(userid, rating.animeid, value) {
+        Ok(applied) => {
+            return Ok(json!({ "applied": applied }));
+        },
+        Err(_) => {
+            return Err(Status::new(500, "Some weird mistake occured, sorry!"));
+        }
+    }
+}
+
 #[get("/users/<userid>/similar?<count>")]
 fn endpoint_similar_users(userid: u64, count: Option<usize>, recom_engine: State<Arc<RecommendationEngine>>) -> Result<JsonValue, Status> {
     match recom_engine.find_k_similar_users(userid, count.unwrap_or(5)) {
@@ -148,6 +563,12 @@ fn endpoint_similar_users(userid: u64, count: Option<usize>, recom_engine: State
                     warn!(target: "Reco-Chan", "User unknown: {}", userid);
                     return Err(Status::NotFound);
                 },
+                PredictionError::Unsupported => {
+                    return Err(Status::new(501, "I only compare animes right now, not users! Don't ask for the impossible!"));
+                },
+                PredictionError::NotInitialized => {
+                    return Err(Status::new(503, "I haven't studied anything yet!"));
+                },
                 _ => {
                     return Err(Status::raw(500));
                 }
@@ -168,6 +589,57 @@ fn endpoint_similar_animes(animeid: u64, count: Option<usize>, recom_engine: Sta
                     warn!(target: "Reco-Chan", "Anime unknown: {}", animeid);
                     return Err(Status::NotFound);
                 },
+                PredictionError::NotInitialized => {
+                    return Err(Status::new(503, "I haven't studied anything yet!"));
+                },
+                _ => {
+                    return Err(Status::raw(500));
+                }
+            }
+        }
+    }
+}
+
+#[get("/features?<count>")]
+fn endpoint_features(count: Option<usize>, recom_engine: State<Arc<RecommendationEngine>>) -> Result<JsonValue, Status> {
+    match recom_engine.feature_summaries(count.unwrap_or(10)) {
+        Ok(features) => {
+            return Ok(json!(features));
+        },
+        Err(e) => {
+            match e {
+                PredictionError::Unsupported => {
+                    return Err(Status::new(501, "I don't have any features right now. Stop staring at me like that!"));
+                },
+                PredictionError::NotInitialized => {
+                    return Err(Status::new(503, "I haven't studied anything yet!"));
+                },
+                _ => {
+                    return Err(Status::raw(500));
+                }
+            }
+        }
+    }
+}
+
+#[get("/users/<userid>/features?<count>")]
+fn endpoint_user_features(userid: u64, count: Option<usize>, recom_engine: State<Arc<RecommendationEngine>>) -> Result<JsonValue, Status> {
+    match recom_engine.dominant_user_features(userid, count.unwrap_or(3)) {
+        Ok(features) => {
+            return Ok(json!(features));
+        },
+        Err(e) => {
+            match e {
+                PredictionError::UnknownUser => {
+                    warn!(target: "Reco-Chan", "User unknown: {}", userid);
+                    return Err(Status::NotFound);
+                },
+                PredictionError::Unsupported => {
+                    return Err(Status::new(501, "I don't have any features right now. Stop staring at me like that!"));
+                },
+                PredictionError::NotInitialized => {
+                    return Err(Status::new(503, "I haven't studied anything yet!"));
+                },
                 _ => {
                     return Err(Status::raw(500));
                 }
@@ -175,3 +647,41 @@ fn endpoint_similar_animes(animeid: u64, count: Option<usize>, recom_engine: Sta
         }
     }
 }
+
+
+#[get("/training/curve")]
+fn endpoint_training_curve(recom_engine: State<Arc<RecommendationEngine>>) -> Result<JsonValue, Status> {
+    match recom_engine.training_curve() {
+        Ok(curve) => {
+            return Ok(json!(curve));
+        },
+        Err(_) => {
+            return Err(Status::new(503, "I haven't studied anything yet!"));
+        }
+    }
+}
+
+#[get("/training/schedule")]
+fn endpoint_training_schedule(scheduler: State<Arc<Scheduler>>) -> JsonValue {
+    return json!(scheduler.status());
+}
+
+#[post("/training/schedule/pause")]
+fn endpoint_pause_training_schedule(scheduler: State<Arc<Scheduler>>) -> JsonValue {
+    scheduler.pause();
+    return json!(scheduler.status());
+}
+
+#[post("/training/schedule/resume")]
+fn endpoint_resume_training_schedule(scheduler: State<Arc<Scheduler>>) -> JsonValue {
+    scheduler.resume();
+    return json!(scheduler.status());
+}
+
+#[post("/training/run")]
+fn endpoint_run_training(scheduler: State<Arc<Scheduler>>) -> Result<JsonValue, Status> {
+    if !scheduler.run_now() {
+        return Err(Status::new(409, "I'm already studying! Don't rush me!"));
+    }
+    return Ok(json!(scheduler.status()));
+}
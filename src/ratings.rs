@@ -4,6 +4,68 @@ pub type Id = u64;
 pub type Index = usize;
 pub type RatingValue = f64;
 
+/// How far (relative to the step) a rating may be off a step, to still count as on it.
+/// This only covers floating point errors, e.g. of `raw * input_factor`.
+const STEP_TOLERANCE: RatingValue = 1e-6;
+
+/// Range and granularity of the ratings, and how the raw values of a data source are mapped into it:
+/// `rating = raw * input_factor + input_offset`.
+#[derive(Clone, Copy)]
+pub struct RatingScale {
+    pub min: RatingValue,
+    pub max: RatingValue,
+    /// Difference between two neighboring ratings on the scale (e.g. 0.5 for half stars). 0 for a continuous scale.
+    pub step: RatingValue,
+    pub input_factor: RatingValue,
+    pub input_offset: RatingValue
+}
+impl Default for RatingScale {
+    /// Ratings from 0 to 5 in half stars, from raw values of 0 to 10.
+    fn default() -> Self {
+        return Self { min: 0.0, max: 5.0, step: 0.5, input_factor: 0.5, input_offset: 0.0 };
+    }
+}
+impl RatingScale {
+    /// Rating of the given raw value from a data source. None if it is not on the scale.
+    pub fn from_raw(&self, raw: RatingValue) -> Option<RatingValue> {
+        return self.snap(raw * self.input_factor + self.input_offset);
+    }
+
+    /// The given rating, snapped exactly onto its step. None if it is not on the scale, i.e.
+    /// outside of min to max, or between two steps (e.g. 3.3 on a scale of half stars).
+    pub fn snap(&self, rating: RatingValue) -> Option<RatingValue> {
+        if !(rating >= self.min && rating <= self.max) {
+            return None;
+        }
+        if self.step <= 0.0 {
+            return Some(rating);
+        }
+        let snapped = self.round(rating);
+        return if (snapped - rating).abs() <= STEP_TOLERANCE * self.step { Some(snapped) } else { None };
+    }
+
+    pub fn contains(&self, rating: RatingValue) -> bool {
+        return self.snap(rating).is_some();
+    }
+
+    /// Rating from which on a rating counts as good: 70% of the way from min to max (3.5 of 5 stars).
+    pub fn good_rating(&self) -> RatingValue {
+        return self.min + 0.7 * (self.max - self.min);
+    }
+
+    pub fn clamp(&self, rating: RatingValue) -> RatingValue {
+        return rating.max(self.min).min(self.max);
+    }
+
+    /// Nearest rating on the scale.
+    pub fn round(&self, rating: RatingValue) -> RatingValue {
+        if self.step <= 0.0 {
+            return self.clamp(rating);
+        }
+        return self.clamp(self.min + ((rating - self.min) / self.step).round() * self.step);
+    }
+}
+
 pub struct Anime {
     pub id: Id
 }
@@ -32,12 +94,76 @@ pub struct RatingContainer {
     pub ratings: Vec<Rating>,
     pub animes: Vec<Anime>,
     pub users: Vec<User>,
+    /// Per user (column): (anime row, rating) of every anime the user rated, sorted by row.
+    pub rated: Vec<Vec<(Index, RatingValue)>>,
     anime2row: HashMap<Id, Index>,
     row2anime: HashMap<Index, Id>,
     user2column: HashMap<Id, Index>,
     column2user: HashMap<Index, Id>
 }
 impl RatingContainer {
+    /// Create a container without any ratings from the given lists of ids.
+    /// The position of an id within its list becomes its row / column index.
+    pub fn from_ids(animeid_list: Vec<Id>, userid_list: Vec<Id>) -> Self {
+        // Generate (back&forth) maps for animes
+        let anime2row: HashMap<Id, Index> = animeid_list.iter().enumerate()
+                    .map(|(idx, &anime_id)| (anime_id, idx as Index) ).collect();
+        let row2anime: HashMap<Index, Id> = animeid_list.iter().enumerate()
+                    .map(|(idx, &anime_id)| (idx as Index, anime_id) ).collect();
+
+        // Generate (back&forth) maps for users
+        let user2column: HashMap<Id, Index> = userid_list.iter().enumerate()
+                    .map(|(idx, &user_id)| (user_id, idx as Index) ).collect();
+        let column2user: HashMap<Index, Id> = userid_list.iter().enumerate()
+                    .map(|(idx, &user_id)| (idx as Index, user_id) ).collect();
+
+        // Generate anime and user arrays
+        let animes: Vec<Anime> = animeid_list.iter().map(|&animeid| Anime::new(animeid)).collect();
+        let users: Vec<User> = userid_list.iter().map(|&userid| User::new(userid)).collect();
+        let rated = vec![Vec::new(); users.len()];
+
+        return Self {
+            ratings: Vec::new(), animes, users, rated,
+            anime2row, row2anime, user2column, column2user
+        };
+    }
+
+    /// Merge the given (animeid, userid, rating) triples into this container. A merged
+    /// rating replaces an existing rating of the same user for the same anime.
+    pub fn merge(self, ratings: &[(Id, Id, RatingValue)]) -> Self {
+        let merged: HashMap<(Id, Id), RatingValue> = ratings.iter()
+                    .map(|&(animeid, userid, rating)| ((animeid, userid), rating)).collect();
+        let mut builder = RatingContainerBuilder::new();
+        for rating in &self.ratings {
+            let (animeid, userid) = (self.animes[rating.animeidx].id, self.users[rating.useridx].id);
+            if !merged.contains_key(&(animeid, userid)) {
+                builder.add_rating(animeid, userid, rating.rating);
+            }
+        }
+        for (&(animeid, userid), &rating) in &merged {
+            builder.add_rating(animeid, userid, rating);
+        }
+        return builder.build();
+    }
+
+    /// Rebuild `rated` from the ratings, which have to be sorted by anime.
+    pub fn rebuild_rated(&mut self) {
+        self.rated = vec![Vec::new(); self.users.len()];
+        // Ratings are sorted by anime, so every user's rated animes end up sorted as well
+        for rating in &self.ratings {
+            self.rated[rating.useridx].push((rating.animeidx, rating.rating));
+        }
+    }
+
+    /// Record that the given user rated the given anime, replacing a previous rating of it.
+    pub fn set_rated(&mut self, useridx: Index, animeidx: Index, rating: RatingValue) {
+        let rated = &mut self.rated[useridx];
+        match rated.binary_search_by_key(&animeidx, |&(a, _)| a) {
+            Ok(pos) => rated[pos].1 = rating,
+            Err(pos) => rated.insert(pos, (animeidx, rating))
+        }
+    }
+
     pub fn anime2row(&self, animeid: Id) -> Option<Index> {
         return self.anime2row.get(&animeid).map(|r| *r);
     }
@@ -76,27 +202,13 @@ impl RatingContainerBuilder {
         // Generate sequential ids for animes
         let mut animeid_list: Vec<Id> = self.anime_ids.into_iter().collect();
         animeid_list.sort();
-        // Generate (back&forth) maps for animes
-        let anime2row: HashMap<Id, Index> = animeid_list.iter().enumerate()
-                    .map(|(idx, &anime_id)| (anime_id, idx as Index) ).collect();
-        let row2anime: HashMap<Index, Id> = animeid_list.iter().enumerate()
-                    .map(|(idx, &anime_id)| (idx as Index, anime_id) ).collect();
-
-        // Generate sequential ids for animes
+        // Generate sequential ids for users
         let mut userid_list: Vec<Id> = self.user_ids.into_iter().collect();
         userid_list.sort();
-        // Generate (back&forth) maps for animes
-        let user2column: HashMap<Id, Index> = userid_list.iter().enumerate()
-                    .map(|(idx, &user_id)| (user_id, idx as Index) ).collect();
-        let column2user: HashMap<Index, Id> = userid_list.iter().enumerate()
-                    .map(|(idx, &user_id)| (idx as Index, user_id) ).collect();
-
-        // Generate anime and user arrays
-        let animes: Vec<Anime> = animeid_list.iter().map(|&animeid| Anime::new(animeid)).collect();
-        let users: Vec<User> = userid_list.iter().map(|&userid| User::new(userid)).collect();
 
+        let mut container = RatingContainer::from_ids(animeid_list, userid_list);
         let mut ratings: Vec<Rating> = self.ratings.into_iter().map(|(animeid, userid, rating)| {
-            Rating { animeidx: anime2row[&animeid], useridx: user2column[&userid], rating: rating }
+            Rating { animeidx: container.anime2row[&animeid], useridx: container.user2column[&userid], rating: rating }
         }).collect();
         // Sort ratings by animeidx, then by useridx
         ratings.sort_unstable_by(|r0,r1| {
@@ -105,10 +217,8 @@ impl RatingContainerBuilder {
             } else { r0.useridx.cmp(&r1.useridx) }
         });
 
-        return RatingContainer {
-            ratings, animes, users,
-            anime2row, row2anime, user2column, column2user
-        };
+        container.ratings = ratings;
+        container.rebuild_rated();
+        return container;
     }
-}
-
+}
\ No newline at end of file
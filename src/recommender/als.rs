@@ -0,0 +1,76 @@
+use nalgebra as na;
+use rand::{SeedableRng, rngs::StdRng};
+use crate::ratings::{Index, RatingValue};
+use super::{Trainer, RecommendationEngineConf, RecommendationEngineState, initial_features};
+use super::progress::{TrainingProgress, PredictionErrors, regularization_penalty};
+
+/// Alternating Least Squares. All features are trained at once, by alternately
+/// fixing the anime features and solving a small ridge-regression problem for
+/// every user, and then fixing the user features and doing the same for every anime.
+pub struct AlsTrainer;
+
+impl Trainer for AlsTrainer {
+    fn train(&self, conf: &RecommendationEngineConf, state: &mut RecommendationEngineState, progress: &mut TrainingProgress) {
+        let (anime_cnt, user_cnt) = (state.ratings.animes.len(), state.ratings.users.len());
+
+        // ALS learns the residual that remains after the baseline prediction
+        let mut ratings_by_anime: Vec<Vec<(Index, RatingValue)>> = vec![Vec::new(); anime_cnt];
+        let mut ratings_by_user: Vec<Vec<(Index, RatingValue)>> = vec![Vec::new(); user_cnt];
+        for rating in &state.ratings.ratings {
+            let (a,u) = (rating.animeidx, rating.useridx);
+            let residual = rating.rating - state.baseline(a, u);
+            ratings_by_anime[a].push((u, residual));
+            ratings_by_user[u].push((a, residual));
+        }
+
+        let mut rng = StdRng::seed_from_u64(conf.seed);
+        state.anime_features = initial_features(conf, anime_cnt, conf.features, &mut rng);
+        state.user_features = na::DMatrix::from_element(conf.features, user_cnt, conf.initial_approximation_value);
+
+        progress.start(None);
+        while progress.running() {
+            for u in 0..user_cnt {
+                if let Some(solution) = Self::solve(conf, &ratings_by_user[u], |a| state.anime_features.row(a).transpose()) {
+                    state.user_features.set_column(u, &solution);
+                }
+            }
+            for a in 0..anime_cnt {
+                if let Some(solution) = Self::solve(conf, &ratings_by_anime[a], |u| state.user_features.column(u).into_owned()) {
+                    state.anime_features.set_row(a, &solution.transpose());
+                }
+            }
+
+            let train = PredictionErrors::measure(&state.ratings.ratings, |_, r| Self::predict(state, r.animeidx, r.useridx));
+            let validation = progress.validate(&state.validation, |_, r| Self::predict(state, r.animeidx, r.useridx));
+            state.approximation_error = train.mae;
+            progress.record(train, validation, regularization_penalty(conf, state, false));
+        }
+    }
+}
+
+impl AlsTrainer {
+    /// Solve the regularized least-squares problem for one user (or anime), given the
+    /// fixed feature vectors of the animes it rated (or the users that rated it).
+    fn solve<F>(conf: &RecommendationEngineConf, ratings: &[(Index, RatingValue)], fixed_features: F) -> Option<na::DVector<RatingValue>>
+                where F: Fn(Index) -> na::DVector<RatingValue> {
+        if ratings.is_empty() { return None; }
+
+        let mut lhs = na::DMatrix::from_element(conf.features, conf.features, 0.0);
+        let mut rhs = na::DVector::from_element(conf.features, 0.0);
+        for &(idx, residual) in ratings {
+            let x = fixed_features(idx);
+            lhs += &x * x.transpose();
+            rhs += &x * residual;
+        }
+        // Weighted-lambda regularization: scale with the amount of ratings
+        let lambda = conf.regularization_parameter * ratings.len() as RatingValue;
+        for f in 0..conf.features {
+            lhs[(f,f)] += lambda;
+        }
+        return lhs.cholesky().map(|cholesky| cholesky.solve(&rhs));
+    }
+
+    fn predict(state: &RecommendationEngineState, a: Index, u: Index) -> RatingValue {
+        return state.baseline(a, u) + state.anime_features.row(a).transpose().dot(&state.user_features.column(u));
+    }
+}
@@ -0,0 +1,59 @@
+use rand::{SeedableRng, rngs::StdRng};
+use crate::ratings::{Index, RatingValue};
+use super::{Trainer, RecommendationEngineConf, RecommendationEngineState, initial_features, shuffle_epoch};
+use super::progress::{TrainingProgress, PredictionErrors, regularization_penalty};
+
+/// Biased matrix factorization. Instead of keeping the damped averages as a fixed
+/// baseline, the global mean, per-anime and per-user biases are learned by SGD
+/// together with all latent features, each with its own regularization.
+pub struct BiasedSgdTrainer;
+
+impl Trainer for BiasedSgdTrainer {
+    fn train(&self, conf: &RecommendationEngineConf, state: &mut RecommendationEngineState, progress: &mut TrainingProgress) {
+        let (anime_cnt, user_cnt) = (state.ratings.animes.len(), state.ratings.users.len());
+        let (learn_rate, reg, bias_reg) = (conf.learn_rate, conf.regularization_parameter, conf.bias_regularization_parameter);
+
+        // Start from the damped averages, split into a global mean and the deviations from it
+        state.global_bias = state.global_rating_avg;
+        state.anime_bias = state.anime_rating_avg.add_scalar(-state.global_rating_avg);
+        state.user_bias = state.user_avg_offset.clone();
+
+        let mut rng = StdRng::seed_from_u64(conf.seed);
+        state.anime_features = initial_features(conf, anime_cnt, conf.features, &mut rng);
+        state.user_features = initial_features(conf, conf.features, user_cnt, &mut rng);
+
+        let mut order: Vec<Index> = (0..state.ratings.ratings.len()).collect();
+        progress.start(None);
+        while progress.running() {
+            shuffle_epoch(conf, &mut order, &mut rng);
+            for &idx in &order {
+                let rating = &state.ratings.ratings[idx];
+                let (a,u) = (rating.animeidx, rating.useridx);
+                let prediction = state.global_bias + state.anime_bias[a] + state.user_bias[u]
+                                    + state.anime_features.row(a).transpose().dot(&state.user_features.column(u));
+                let err = rating.rating - prediction;
+
+                state.global_bias += learn_rate * err;
+                state.anime_bias[a] += learn_rate * (err - bias_reg * state.anime_bias[a]);
+                state.user_bias[u] += learn_rate * (err - bias_reg * state.user_bias[u]);
+                for f in 0..conf.features {
+                    let af = state.anime_features[(a,f)];
+                    let uf = state.user_features[(f,u)];
+                    state.anime_features[(a,f)] += learn_rate * (err * uf - reg * af);
+                    state.user_features[(f,u)] += learn_rate * (err * af - reg * uf);
+                }
+            }
+
+            let train = PredictionErrors::measure(&state.ratings.ratings, |_, r| Self::predict(state, r.animeidx, r.useridx));
+            let validation = progress.validate(&state.validation, |_, r| Self::predict(state, r.animeidx, r.useridx));
+            state.approximation_error = train.mae;
+            progress.record(train, validation, regularization_penalty(conf, state, true));
+        }
+    }
+}
+
+impl BiasedSgdTrainer {
+    fn predict(state: &RecommendationEngineState, a: Index, u: Index) -> RatingValue {
+        return state.baseline(a, u) + state.anime_features.row(a).transpose().dot(&state.user_features.column(u));
+    }
+}
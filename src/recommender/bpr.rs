@@ -0,0 +1,112 @@
+use nalgebra as na;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use crate::ratings::{Index, RatingValue};
+use super::{Trainer, RecommendationEngineConf, RecommendationEngineState, initial_features, shuffle_epoch};
+use super::progress::{TrainingProgress, PredictionErrors, regularization_penalty};
+
+/// Bayesian Personalized Ranking (Rendle et al., 2009) for implicit feedback. Every rating of at
+/// least `positive_threshold` is an interaction the user liked (e.g. watched or completed). Instead of
+/// predicting rating values, SGD learns to score each of a user's positive animes higher than
+/// a randomly sampled anime the user didn't interact with. The anime biases capture popularity,
+/// global and user biases would cancel out in the comparison and stay 0.
+pub struct BprTrainer;
+
+/// (user, positive anime, negative anime)
+type Triple = (Index, Index, Index);
+
+impl Trainer for BprTrainer {
+    fn train(&self, conf: &RecommendationEngineConf, state: &mut RecommendationEngineState, progress: &mut TrainingProgress) {
+        let (anime_cnt, user_cnt) = (state.ratings.animes.len(), state.ratings.users.len());
+        let (learn_rate, reg, bias_reg) = (conf.learn_rate, conf.regularization_parameter, conf.bias_regularization_parameter);
+
+        let positives: Vec<(Index, Index)> = state.ratings.ratings.iter()
+                .filter(|r| r.rating >= conf.positive_threshold)
+                .map(|r| (r.useridx, r.animeidx)).collect();
+        let mut user_positives: Vec<Vec<Index>> = vec![Vec::new(); user_cnt];
+        for &(u, a) in &positives {
+            user_positives[u].push(a);
+        }
+        // Ratings are sorted by anime, but a data source may deliver the same interaction more than once
+        for positives in &mut user_positives {
+            positives.dedup();
+        }
+
+        state.global_bias = 0.0;
+        state.anime_bias = na::DVector::from_element(anime_cnt, 0.0);
+        state.user_bias = na::DVector::from_element(user_cnt, 0.0);
+        let mut rng = StdRng::seed_from_u64(conf.seed);
+        state.anime_features = initial_features(conf, anime_cnt, conf.features, &mut rng);
+        state.user_features = initial_features(conf, conf.features, user_cnt, &mut rng);
+
+        // The curve is measured on fixed triples, so that epochs are comparable
+        let train_triples: Vec<Triple> = positives.iter()
+                .filter_map(|&(u, a)| Self::sample_negative(&user_positives[u], anime_cnt, &mut rng).map(|n| (u, a, n)))
+                .collect();
+        let validation_triples: Vec<Triple> = state.validation.iter()
+                .filter(|r| r.rating >= conf.positive_threshold)
+                .filter_map(|r| Self::sample_negative(&user_positives[r.useridx], anime_cnt, &mut rng).map(|n| (r.useridx, r.animeidx, n)))
+                .collect();
+
+        let mut order: Vec<Index> = (0..positives.len()).collect();
+        progress.start(None);
+        while progress.running() {
+            shuffle_epoch(conf, &mut order, &mut rng);
+            for &idx in &order {
+                let (u, i) = positives[idx];
+                let j = match Self::sample_negative(&user_positives[u], anime_cnt, &mut rng) {
+                    Some(j) => j,
+                    None => continue
+                };
+
+                // Gradient of ln σ(x_uij), where x_uij = score(u, i) - score(u, j)
+                let g = 1.0 / (1.0 + Self::score_difference(state, (u, i, j)).exp());
+                state.anime_bias[i] += learn_rate * (g - bias_reg * state.anime_bias[i]);
+                state.anime_bias[j] += learn_rate * (-g - bias_reg * state.anime_bias[j]);
+                for f in 0..conf.features {
+                    let (uf, pf, nf) = (state.user_features[(f,u)], state.anime_features[(i,f)], state.anime_features[(j,f)]);
+                    state.user_features[(f,u)] += learn_rate * (g * (pf - nf) - reg * uf);
+                    state.anime_features[(i,f)] += learn_rate * (g * uf - reg * pf);
+                    state.anime_features[(j,f)] += learn_rate * (-g * uf - reg * nf);
+                }
+            }
+
+            let train = Self::ranking_errors(state, &train_triples);
+            let validation = if validation_triples.is_empty() { None } else { Some(Self::ranking_errors(state, &validation_triples)) };
+            state.approximation_error = train.mae;
+            progress.record(train, validation, regularization_penalty(conf, state, true));
+        }
+    }
+}
+
+impl BprTrainer {
+    /// Uniformly sampled anime that is not one of the given (sorted) positives.
+    fn sample_negative(positives: &[Index], anime_cnt: usize, rng: &mut StdRng) -> Option<Index> {
+        if positives.len() >= anime_cnt {
+            return None;
+        }
+        loop {
+            let candidate = rng.gen_range(0, anime_cnt);
+            if positives.binary_search(&candidate).is_err() {
+                return Some(candidate);
+            }
+        }
+    }
+
+    fn score_difference(state: &RecommendationEngineState, (u, i, j): Triple) -> RatingValue {
+        let user_vector = state.user_features.column(u);
+        return state.anime_bias[i] - state.anime_bias[j]
+                + (state.anime_features.row(i) - state.anime_features.row(j)).transpose().dot(&user_vector);
+    }
+
+    /// Errors of the pairwise rankings: For every triple, the error is 1 - σ(x_uij),
+    /// i.e. how likely the model ranks the negative anime above the positive one.
+    fn ranking_errors(state: &RecommendationEngineState, triples: &[Triple]) -> PredictionErrors {
+        let (mut absolute_error_sum, mut squared_error_sum) = (0.0, 0.0);
+        for &triple in triples {
+            let err = 1.0 / (1.0 + Self::score_difference(state, triple).exp());
+            absolute_error_sum += err;
+            squared_error_sum += err * err;
+        }
+        return PredictionErrors::from_sums(absolute_error_sum, squared_error_sum, triples.len().max(1));
+    }
+}
@@ -0,0 +1,88 @@
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use crate::ratings::{Id, RatingValue};
+
+/// Non-personalized recommendations for users that are unknown to the model.
+#[derive(Clone)]
+pub enum FallbackStrategy {
+    /// Animes with the highest damped average rating.
+    Popular,
+    /// Animes that received the most ratings through `ingest_rating()` within the last `window_secs`.
+    Trending { window_secs: u64 },
+    /// A fixed list of animes, in the given order.
+    Curated { animes: Vec<Id> }
+}
+impl FallbackStrategy {
+    pub fn name(&self) -> &'static str {
+        return match self {
+            FallbackStrategy::Popular => "Popular",
+            FallbackStrategy::Trending { .. } => "Trending",
+            FallbackStrategy::Curated { .. } => "Curated"
+        };
+    }
+}
+
+#[derive(Serialize)]
+pub struct FallbackRecommendation {
+    pub animeid: Id,
+    /// Damped average rating (`Popular`) or amount of recent ratings (`Trending`). None for `Curated`.
+    pub score: Option<RatingValue>
+}
+
+/// Recommendations for a user, personal ones as well as fallback ones, so clients can tell them apart.
+#[derive(Serialize)]
+pub struct RecommendationResult<R> {
+    pub personalized: bool,
+    /// Name of the fallback strategy that produced the recommendations. Only present for fallback recommendations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<&'static str>,
+    pub recommendations: Vec<R>
+}
+impl<R> RecommendationResult<R> {
+    pub fn personal(recommendations: Vec<R>) -> Self {
+        return Self { personalized: true, strategy: None, recommendations };
+    }
+
+    pub fn fallback(strategy: &FallbackStrategy, recommendations: Vec<R>) -> Self {
+        return Self { personalized: false, strategy: Some(strategy.name()), recommendations };
+    }
+}
+pub type FallbackResult = RecommendationResult<FallbackRecommendation>;
+
+
+/// Log of recently ingested ratings as (unix timestamp, animeid), oldest first.
+/// Only ratings within `window_secs` are kept.
+pub struct RecentRatings {
+    window_secs: u64,
+    ratings: VecDeque<(u64, Id)>
+}
+impl RecentRatings {
+    /// Log that keeps ratings as long as the longest `Trending` window of the given strategies needs them.
+    pub fn new(strategies: &[FallbackStrategy]) -> Self {
+        let window_secs = strategies.iter().map(|strategy| match strategy {
+            FallbackStrategy::Trending { window_secs } => *window_secs,
+            _ => 0
+        }).max().unwrap_or(0);
+        return Self { window_secs, ratings: VecDeque::new() };
+    }
+
+    pub fn push(&mut self, now: u64, animeid: Id) {
+        if self.window_secs == 0 { return; }
+        self.ratings.push_back((now, animeid));
+        while self.ratings.front().map_or(false, |&(time, _)| time + self.window_secs < now) {
+            self.ratings.pop_front();
+        }
+    }
+
+    /// Animes ordered by the amount of ratings they got within the last `window_secs`, most first.
+    pub fn trending(&self, now: u64, window_secs: u64) -> Vec<(Id, usize)> {
+        let mut counts: HashMap<Id, usize> = HashMap::new();
+        for &(_, animeid) in self.ratings.iter().filter(|&&(time, _)| time + window_secs >= now) {
+            *counts.entry(animeid).or_insert(0) += 1;
+        }
+        let mut trending: Vec<(Id, usize)> = counts.into_iter().collect();
+        // Ties are broken by id, so the order doesn't depend on the hashing
+        trending.sort_by(|t0, t1| t1.1.cmp(&t0.1).then(t0.0.cmp(&t1.0)));
+        return trending;
+    }
+}
@@ -0,0 +1,78 @@
+use rand::{SeedableRng, rngs::StdRng};
+use crate::ratings::{Index, RatingValue};
+use super::{Trainer, RecommendationEngineConf, RecommendationEngineState, initial_features, shuffle_epoch};
+use super::parallel::StratifiedEpoch;
+use super::progress::{TrainingProgress, PredictionErrors, regularization_penalty};
+
+/// Simon Funk's sequential SGD. The features are trained one after another,
+/// each on the residual that the previously trained features left behind.
+/// With more than one configured thread, each epoch is run by `StratifiedEpoch`.
+pub struct FunkSvdTrainer;
+
+impl Trainer for FunkSvdTrainer {
+    fn train(&self, conf: &RecommendationEngineConf, state: &mut RecommendationEngineState, progress: &mut TrainingProgress) {
+        let ratings = &state.ratings.ratings;
+
+        let mut rng = StdRng::seed_from_u64(conf.seed);
+        state.anime_features = initial_features(conf, state.anime_bias.len(), conf.features, &mut rng);
+        state.user_features = initial_features(conf, conf.features, state.user_bias.len(), &mut rng);
+        let mut order: Vec<Index> = (0..ratings.len()).collect();
+        let mut residual_cache: Vec<_> = ratings.iter()
+                .map(|r| state.baseline(r.animeidx, r.useridx)).collect();
+        let mut validation_residual_cache: Vec<_> = state.validation.iter()
+                .map(|r| state.baseline(r.animeidx, r.useridx)).collect();
+
+        for f in 0..conf.features {
+            debug!(target: "RecommendationEngine", "Training feature {}...", f);
+            progress.start(Some(f));
+
+            let parallel_epoch = match conf.threads {
+                1 => None,
+                _ => Some(StratifiedEpoch::new(conf, state, &residual_cache, f))
+            };
+            while progress.running() {
+                if let Some(parallel_epoch) = &parallel_epoch {
+                    parallel_epoch.run(&mut state.anime_features, &mut state.user_features, f, &mut rng);
+                } else {
+                    shuffle_epoch(conf, &mut order, &mut rng);
+                    for &idx in &order {
+                        let rating = &ratings[idx];
+                        let (a,u) = (rating.animeidx, rating.useridx);
+
+                        let prediction = residual_cache[idx] + state.anime_features[(a,f)] * state.user_features[(f,u)];
+                        let err = rating.rating - prediction;
+
+                        let af = state.anime_features[(a,f)];
+                        let uf = state.user_features[(f,u)];
+                        state.anime_features[(a,f)] += conf.learn_rate * (err * uf - conf.regularization_parameter * af);
+                        state.user_features[(f,u)] += conf.learn_rate * (err * af - conf.regularization_parameter * uf);
+                    }
+                }
+
+                let train = PredictionErrors::measure(ratings, |idx, r| Self::predict(state, &residual_cache, idx, r.animeidx, r.useridx, f));
+                let validation = progress.validate(&state.validation, |idx, r| Self::predict(state, &validation_residual_cache, idx, r.animeidx, r.useridx, f));
+                state.approximation_error = train.mae;
+                progress.record(train, validation, regularization_penalty(conf, state, false));
+            }
+
+            // Apply trained model to cache and continue with next round
+            for (idx, rating) in ratings.iter().enumerate() {
+                residual_cache[idx] = (conf.prediction_sanitizer)(
+                    Self::predict(state, &residual_cache, idx, rating.animeidx, rating.useridx, f), &conf.rating_scale
+                );
+            }
+            for (idx, rating) in state.validation.iter().enumerate() {
+                validation_residual_cache[idx] = (conf.prediction_sanitizer)(
+                    Self::predict(state, &validation_residual_cache, idx, rating.animeidx, rating.useridx, f), &conf.rating_scale
+                );
+            }
+        }
+    }
+}
+
+impl FunkSvdTrainer {
+    /// Prediction for a rating, with all features up to (and including) `f`.
+    fn predict(state: &RecommendationEngineState, residual_cache: &[RatingValue], idx: usize, a: usize, u: usize, f: usize) -> RatingValue {
+        return residual_cache[idx] + state.anime_features[(a,f)] * state.user_features[(f,u)];
+    }
+}
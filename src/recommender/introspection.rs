@@ -0,0 +1,79 @@
+use nalgebra as na;
+use serde_derive::Serialize;
+use std::cmp::Ordering;
+use crate::ratings::{Id, Index, RatingValue};
+use super::RecommendationEngineState;
+
+#[derive(Serialize)]
+pub struct FeatureLoading{ pub animeid: Id, pub loading: RatingValue }
+
+/// What one latent feature stands for, judged by the animes that "contain" it the most and the least.
+#[derive(Serialize)]
+pub struct FeatureSummary {
+    pub feature: Index,
+    /// Label of the feature from `RecommendationEngineConf::feature_labels`, if it has one.
+    pub label: Option<String>,
+    /// Share of the latent part of all predictions that is due to this feature.
+    pub variance_explained: RatingValue,
+    /// Animes with the highest values of this feature, highest first.
+    pub highest: Vec<FeatureLoading>,
+    /// Animes with the lowest values of this feature, lowest first.
+    pub lowest: Vec<FeatureLoading>
+}
+
+/// A feature that has a large influence on the predictions of a user.
+#[derive(Serialize)]
+pub struct DominantFeature {
+    pub feature: Index,
+    pub label: Option<String>,
+    /// How much the feature changes the user's predictions, for an anime with a typical (root mean
+    /// square) value of the feature. Negative if the user dislikes animes that contain the feature.
+    pub weight: RatingValue
+}
+
+
+fn label(labels: &[String], feature: Index) -> Option<String> {
+    return labels.get(feature).filter(|label| !label.is_empty()).cloned();
+}
+
+/// Root mean square of every feature over all animes.
+fn feature_scales(state: &RecommendationEngineState) -> Vec<RatingValue> {
+    let anime_cnt = state.anime_features.nrows().max(1) as RatingValue;
+    return state.anime_features.column_iter().map(|column| (column.norm_squared() / anime_cnt).sqrt()).collect();
+}
+
+/// Summaries of every feature with the `count` highest and lowest animes. The latent part of the predictions
+/// is the sum of one anime x user matrix per feature. A feature's variance explained is the share of its
+/// matrix in the sum of the squared norms of all of them - which only adds up exactly for uncorrelated features.
+pub fn summarize_features(state: &RecommendationEngineState, labels: &[String], count: usize) -> Vec<FeatureSummary> {
+    let user_vectors = state.user_vectors();
+    let energies: Vec<RatingValue> = state.anime_features.column_iter().enumerate().map(|(f, column)| {
+        column.norm_squared() * user_vectors.iter().map(|v| v[f] * v[f]).sum::<RatingValue>()
+    }).collect();
+    let total_energy: RatingValue = energies.iter().sum();
+
+    return (0..state.anime_features.ncols()).map(|f| {
+        let mut loadings: Vec<(Index, RatingValue)> = state.anime_features.column(f).iter().cloned().enumerate().collect();
+        loadings.sort_by(|l0, l1| l1.1.partial_cmp(&l0.1).unwrap_or(Ordering::Equal));
+        let to_loading = |&(a, loading): &(Index, RatingValue)| FeatureLoading { animeid: state.ratings.row2anime(a).unwrap(), loading };
+        FeatureSummary {
+            feature: f,
+            label: label(labels, f),
+            variance_explained: if total_energy > 0.0 { energies[f] / total_energy } else { 0.0 },
+            highest: loadings.iter().take(count).map(to_loading).collect(),
+            lowest: loadings.iter().rev().take(count).map(to_loading).collect()
+        }
+    }).collect();
+}
+
+/// The `count` features with the largest influence on the predictions for the given user vector, largest first.
+pub fn dominant_features(state: &RecommendationEngineState, labels: &[String], user_vector: &na::DVector<RatingValue>, count: usize) -> Vec<DominantFeature> {
+    let mut dominant: Vec<DominantFeature> = feature_scales(state).into_iter().enumerate().map(|(f, scale)| DominantFeature {
+        feature: f,
+        label: label(labels, f),
+        weight: user_vector[f] * scale
+    }).collect();
+    dominant.sort_by(|d0, d1| d1.weight.abs().partial_cmp(&d0.weight.abs()).unwrap_or(Ordering::Equal));
+    dominant.truncate(count);
+    return dominant;
+}
@@ -0,0 +1,120 @@
+use nalgebra as na;
+use serde_derive::Serialize;
+use crate::ratings::{Index, RatingValue};
+use super::{Trainer, RecommendationEngineConf, RecommendationEngineState};
+use super::progress::{TrainingProgress, PredictionErrors};
+
+/// Similarity between the ratings of two animes, over the users that rated both.
+#[derive(Serialize, Clone, Copy)]
+pub enum NeighborhoodSimilarity {
+    /// Cosine of the ratings, after subtracting every user's average rating.
+    AdjustedCosine,
+    /// Correlation of the ratings, i.e. their cosine after subtracting every anime's average rating.
+    Pearson
+}
+
+/// Most similar animes of every anime. A prediction is the baseline plus the similarity-weighted
+/// average of how much the user deviated from the baseline on the most similar animes that they rated.
+/// The ratings of every user are the ones in `RatingContainer::rated`.
+pub struct ItemNeighborhood {
+    /// Per anime: (anime, similarity) of its most similar animes, most similar first.
+    pub neighbors: Vec<Vec<(Index, RatingValue)>>
+}
+impl ItemNeighborhood {
+    /// Predicted rating of one anime, for a user with the given ratings (sorted by anime) and bias.
+    pub fn predict_one(&self, state: &RecommendationEngineState, user_ratings: &[(Index, RatingValue)], user_bias: RatingValue, animeidx: Index) -> RatingValue {
+        let (mut weighted_sum, mut weight_sum) = (0.0, 0.0);
+        for &(neighbor, similarity) in &self.neighbors[animeidx] {
+            if let Ok(pos) = user_ratings.binary_search_by_key(&neighbor, |&(a, _)| a) {
+                let residual = user_ratings[pos].1 - (state.global_bias + state.anime_bias[neighbor] + user_bias);
+                weighted_sum += similarity * residual;
+                weight_sum += similarity;
+            }
+        }
+        let baseline = state.global_bias + state.anime_bias[animeidx] + user_bias;
+        return if weight_sum > 0.0 { baseline + weighted_sum / weight_sum } else { baseline };
+    }
+
+    /// Predicted ratings of all animes, for a user with the given ratings (sorted by anime) and bias.
+    pub fn predict(&self, state: &RecommendationEngineState, user_ratings: &[(Index, RatingValue)], user_bias: RatingValue) -> na::DVector<RatingValue> {
+        return na::DVector::from_fn(self.neighbors.len(), |a, _| self.predict_one(state, user_ratings, user_bias, a));
+    }
+}
+
+
+/// Classic item-based collaborative filtering. Instead of latent features, the similarity
+/// of every pair of animes is calculated directly from the ratings of the users that rated both.
+/// Similarities that are based on only a few of these users are shrunk towards 0.
+pub struct ItemKnnTrainer;
+
+impl Trainer for ItemKnnTrainer {
+    fn train(&self, conf: &RecommendationEngineConf, state: &mut RecommendationEngineState, progress: &mut TrainingProgress) {
+        let (anime_cnt, user_cnt) = (state.ratings.animes.len(), state.ratings.users.len());
+
+        let mut ratings_by_anime: Vec<Vec<(Index, RatingValue)>> = vec![Vec::new(); anime_cnt];
+        for rating in &state.ratings.ratings {
+            ratings_by_anime[rating.animeidx].push((rating.useridx, rating.rating));
+        }
+        let user_ratings = &state.ratings.rated;
+        let user_avg: Vec<RatingValue> = user_ratings.iter().map(|ratings| match ratings.len() {
+            0 => 0.0,
+            cnt => ratings.iter().map(|&(_, rating)| rating).sum::<RatingValue>() / cnt as RatingValue
+        }).collect();
+        let centered = |a: Index, u: Index, rating: RatingValue| match conf.neighborhood_similarity {
+            NeighborhoodSimilarity::AdjustedCosine => rating - user_avg[u],
+            NeighborhoodSimilarity::Pearson => rating - state.anime_rating_avg[a]
+        };
+
+        // Sums over the co-ratings of the current anime with every other anime
+        let (mut products, mut squares, mut other_squares) = (vec![0.0; anime_cnt], vec![0.0; anime_cnt], vec![0.0; anime_cnt]);
+        let mut co_rating_cnt = vec![0usize; anime_cnt];
+        let mut neighbors = Vec::with_capacity(anime_cnt);
+        for a in 0..anime_cnt {
+            let mut co_rated = Vec::new();
+            for &(u, rating) in &ratings_by_anime[a] {
+                let x = centered(a, u, rating);
+                for &(b, other_rating) in &user_ratings[u] {
+                    if b == a { continue; }
+                    let y = centered(b, u, other_rating);
+                    if co_rating_cnt[b] == 0 { co_rated.push(b); }
+                    products[b] += x * y;
+                    squares[b] += x * x;
+                    other_squares[b] += y * y;
+                    co_rating_cnt[b] += 1;
+                }
+            }
+
+            // Only positively correlated animes make sense as neighbors
+            let mut similar: Vec<(Index, RatingValue)> = Vec::new();
+            for &b in &co_rated {
+                let norm = (squares[b] * other_squares[b]).sqrt();
+                let cnt = co_rating_cnt[b] as RatingValue;
+                let similarity = if norm > 0.0 { products[b] / norm * cnt / (cnt + conf.neighborhood_shrinkage) } else { 0.0 };
+                if similarity > 0.0 {
+                    similar.push((b, similarity));
+                }
+                products[b] = 0.0; squares[b] = 0.0; other_squares[b] = 0.0; co_rating_cnt[b] = 0;
+            }
+            similar.sort_by(|s0, s1| s1.1.partial_cmp(&s0.1).unwrap_or(std::cmp::Ordering::Equal));
+            similar.truncate(conf.neighborhood_size);
+            neighbors.push(similar);
+        }
+        debug!(target: "RecommendationEngine", "Calculated neighborhoods of {} animes", anime_cnt);
+
+        // There are no latent features in this model
+        state.anime_features = na::DMatrix::from_element(anime_cnt, 0, 0.0);
+        state.user_features = na::DMatrix::from_element(0, user_cnt, 0.0);
+
+        let neighborhood = ItemNeighborhood { neighbors };
+        progress.start(None);
+        let train = PredictionErrors::measure(&state.ratings.ratings, |_, r| {
+            neighborhood.predict_one(state, &state.ratings.rated[r.useridx], state.user_bias[r.useridx], r.animeidx)
+        });
+        let validation = progress.validate(&state.validation, |_, r| {
+            neighborhood.predict_one(state, &state.ratings.rated[r.useridx], state.user_bias[r.useridx], r.animeidx)
+        });
+        state.approximation_error = train.mae;
+        progress.record(train, validation, 0.0);
+        state.item_neighborhood = Some(neighborhood);
+    }
+}
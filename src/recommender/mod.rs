@@ -0,0 +1,1192 @@
+use nalgebra as na;
+use serde_derive::{Serialize, Deserialize};
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom, distributions::Normal};
+use std::{fmt, fs, cmp::{Ordering, Reverse}, collections::BinaryHeap, sync::{RwLock, Mutex}, path::{Path, PathBuf}};
+use crate::ratings::{Id, Index, Rating, RatingValue, RatingScale, RatingContainer};
+use crate::dataprovider::{RatingDataProvider, ProviderError};
+use crate::snapshot::{EngineSnapshot, MatrixSnapshot, SnapshotError, SNAPSHOT_VERSION};
+
+mod funksvd;
+mod parallel;
+mod als;
+mod biased;
+mod svdpp;
+mod progress;
+mod similarity;
+mod itemknn;
+mod bpr;
+mod fallback;
+mod introspection;
+use self::funksvd::FunkSvdTrainer;
+use self::als::AlsTrainer;
+use self::biased::BiasedSgdTrainer;
+use self::svdpp::SvdPlusPlusTrainer;
+use self::itemknn::{ItemKnnTrainer, ItemNeighborhood};
+pub use self::itemknn::NeighborhoodSimilarity;
+use self::bpr::BprTrainer;
+use self::fallback::{RecentRatings, FallbackRecommendation};
+pub use self::fallback::{FallbackStrategy, FallbackResult, RecommendationResult};
+pub use self::introspection::{FeatureSummary, DominantFeature};
+use self::progress::TrainingProgress;
+pub use self::progress::TrainingCurve;
+use self::similarity::SimilarityIndex;
+pub use self::similarity::{SimilarityMetric, NeighborIndexKind, IndexBenchmark, format_table as format_benchmark_table};
+
+#[derive(Debug)]
+pub enum PredictionError {
+    Unknown,
+    NotInitialized,
+    UnknownUser,
+    UnknownAnime,
+    Unsupported
+}
+impl fmt::Display for PredictionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PredictionError::Unknown => write!(f, "An unknown error occured during prediction."),
+            PredictionError::NotInitialized => write!(f, "RecommendationEngine needs to be initialized by calling retrain() first."),
+            PredictionError::UnknownUser => write!(f, "Couldn't find a user with this id"),
+            PredictionError::UnknownAnime => write!(f, "Couldn't find an anime with this id"),
+            PredictionError::Unsupported => write!(f, "This is not supported by the trained model")
+        }
+    }
+}
+
+
+#[derive(Serialize, Clone)]
+pub struct UserRatingPrediction{
+    pub animeid: Id,
+    pub rating: RatingValue,
+    /// The user's actual rating, if they already rated the anime.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rated: Option<RatingValue>,
+    /// Why the anime is recommended, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<PredictionExplanation>
+}
+pub type UserRatingPredictionResult = Vec<UserRatingPrediction>;
+/// Breakdown of a prediction into its parts, which add up to the predicted rating (before it is sanitized).
+#[derive(Serialize, Clone)]
+pub struct PredictionExplanation {
+    /// Average of all ratings (0 for ranking models).
+    pub global_average: RatingValue,
+    /// How much better or worse than average the anime is rated.
+    pub anime_bias: RatingValue,
+    /// How much better or worse than average the user rates.
+    pub user_offset: RatingValue,
+    /// How well the anime matches the user's taste, according to the latent features
+    /// (or the neighborhood, for item-kNN models).
+    pub latent_factors: RatingValue,
+    /// Animes the user rated at least as good as their average rating, that are the most similar to this one.
+    pub similar_rated: Vec<SimilarRatedAnime>
+}
+#[derive(Serialize, Clone)]
+pub struct SimilarRatedAnime{ pub animeid: Id, pub rating: RatingValue, pub similarity: RatingValue }
+/// Orders predictions by their rating, ties are broken by anime id.
+struct RankedPrediction(UserRatingPrediction);
+impl PartialEq for RankedPrediction {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+impl Eq for RankedPrediction {}
+impl PartialOrd for RankedPrediction {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+impl Ord for RankedPrediction {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self.0.rating.partial_cmp(&other.0.rating).unwrap_or(Ordering::Equal)
+                    .then(other.0.animeid.cmp(&self.0.animeid));
+    }
+}
+#[derive(Serialize)]
+pub struct SimilarUser{ pub userid: Id, pub similarity: RatingValue }
+pub type SimilarUserResult = Vec<SimilarUser>;
+#[derive(Serialize)]
+pub struct SimilarAnime{ pub animeid: Id, pub similarity: RatingValue }
+pub type SimilarAnimeResult = Vec<SimilarAnime>;
+#[derive(Serialize)]
+pub struct FoldInResult{ pub recommendations: UserRatingPredictionResult, pub similar_users: SimilarUserResult }
+
+
+pub type PredictionSanitizerFn = dyn Fn(RatingValue, &RatingScale) -> RatingValue + Send + Sync;
+pub static PREDICTION_SANITIZER_NOOP: &PredictionSanitizerFn = &|rating_value: RatingValue, _: &RatingScale| {
+    return rating_value;
+};
+pub static PREDICTION_SANITIZER_CLAMP: &PredictionSanitizerFn = &|rating_value: RatingValue, scale: &RatingScale| {
+    return scale.clamp(rating_value);
+};
+
+
+/// Algorithm that is used to train the model. All but `ItemKnn` train anime and user feature matrices.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TrainerKind {
+    /// Simon Funk's sequential, feature-by-feature SGD.
+    FunkSvd,
+    /// Alternating least squares, training all features at once.
+    Als,
+    /// SGD that trains all features at once, together with the global,
+    /// per-anime and per-user biases of the baseline prediction.
+    BiasedSgd,
+    /// Like `BiasedSgd`, but additionally learns implicit feedback features
+    /// from which animes every user rated, regardless of the rating value.
+    SvdPlusPlus,
+    /// Item-based neighborhood model, which predicts from the user's ratings of the most
+    /// similar animes (see `ItemKnnTrainer`). It has no user features, so there are no similar users.
+    ItemKnn,
+    /// Bayesian Personalized Ranking on implicit feedback (see `BprTrainer`). Its
+    /// predictions are ranking scores, which are only comparable among each other.
+    Bpr
+}
+impl TrainerKind {
+    /// Whether the trained model predicts rating values, rather than ranking scores.
+    pub fn predicts_ratings(&self) -> bool {
+        return match self {
+            TrainerKind::Bpr => false,
+            _ => true
+        };
+    }
+}
+
+/// A training algorithm for the latent factor model. Trainers get a state with
+/// initialized rating statistics and baseline, and fill `anime_features` (animes x features)
+/// and `user_features` (features x users), such that the baseline plus the product of
+/// both matrices approximates the ratings. Trainers may also refine the baseline.
+/// The epoch loops are controlled by `progress`, which also records the training curve.
+trait Trainer {
+    fn train(&self, conf: &RecommendationEngineConf, state: &mut RecommendationEngineState, progress: &mut TrainingProgress);
+}
+
+
+/// Distribution that the feature values are drawn from before training.
+#[derive(Serialize, Clone, Copy)]
+pub enum FeatureInitialization {
+    /// All values are `initial_approximation_value`.
+    Constant,
+    /// Uniformly distributed within `initial_approximation_value ± initialization_scale`.
+    Uniform,
+    /// Normally distributed around `initial_approximation_value`, with `initialization_scale` as standard deviation.
+    Normal
+}
+
+/// Feature matrix initialized as configured. Trainers that train all features at once can't
+/// start with identical values for every feature, because all features would then receive
+/// identical updates and converge to the same solution. For them, `Constant` values are jittered.
+fn initial_features(conf: &RecommendationEngineConf, nrows: usize, ncols: usize, rng: &mut StdRng) -> na::DMatrix<RatingValue> {
+    let (initial, scale) = (conf.initial_approximation_value, conf.initialization_scale);
+    let sequential = match conf.trainer { TrainerKind::FunkSvd => true, _ => false };
+    return match conf.initialization {
+        FeatureInitialization::Constant if sequential => na::DMatrix::from_element(nrows, ncols, initial),
+        FeatureInitialization::Constant => na::DMatrix::from_fn(nrows, ncols, |_,_| initial * rng.gen_range(0.5, 1.5)),
+        // gen_range() panics on an empty range
+        FeatureInitialization::Uniform if scale <= 0.0 => na::DMatrix::from_element(nrows, ncols, initial),
+        FeatureInitialization::Uniform => na::DMatrix::from_fn(nrows, ncols, |_,_| initial + rng.gen_range(-scale, scale)),
+        FeatureInitialization::Normal => {
+            let normal = Normal::new(initial, scale);
+            na::DMatrix::from_fn(nrows, ncols, |_,_| rng.sample(normal))
+        }
+    };
+}
+
+/// Shuffle the order in which an epoch visits the ratings, if configured. Visiting them in the order
+/// of `RatingContainerBuilder::build()` every epoch biases the updates towards low anime ids.
+fn shuffle_epoch<T>(conf: &RecommendationEngineConf, items: &mut [T], rng: &mut StdRng) {
+    if conf.shuffle {
+        items.shuffle(rng);
+    }
+}
+
+
+/// Configuration structure that contains a couple of parameters
+/// internally used by the RecommendationEngine. The default values
+/// are the ones providing the best result when using the test data.
+#[derive(Clone)]
+pub struct RecommendationEngineConf {
+    pub trainer: TrainerKind,
+    /// Amount of latent features that are trained per anime / user.
+    pub features: usize,
+    /// Human readable names of the features, by feature index. Empty names mark unlabeled features.
+    pub feature_labels: Vec<String>,
+    /// Step size of the gradient descent.
+    pub learn_rate: RatingValue,
+    /// Amount of threads the SGD epochs of `TrainerKind::FunkSvd` are run on.
+    pub threads: usize,
+    /// Minimum amount of epochs that are trained per feature (or iterations, for ALS).
+    pub min_steps: usize,
+    /// Maximum amount of epochs that are trained per feature (or iterations, for ALS).
+    pub max_steps: usize,
+    /// Training of a feature stops after `min_steps`, once an epoch improves the error by less than this.
+    pub min_improvement: RatingValue,
+    /// Fraction of the ratings that is held out of the training, to stop training once the
+    /// error on them stops improving (instead of the training error). 0 disables this.
+    pub validation_fraction: RatingValue,
+    pub regularization_parameter: RatingValue,
+    /// Regularization of the biases learned by `TrainerKind::BiasedSgd`.
+    pub bias_regularization_parameter: RatingValue,
+    pub prediction_sanitizer: &'static PredictionSanitizerFn,
+    /// Range of the ratings, that predictions are sanitized into.
+    pub rating_scale: RatingScale,
+    /// Whether served predictions are rounded to the nearest rating on `rating_scale`.
+    pub round_predictions: bool,
+    /// Metric that similar users / animes are found by.
+    pub similarity: SimilarityMetric,
+    /// Index that similar users / animes are searched with.
+    pub neighbor_index: NeighborIndexKind,
+    /// Value that all feature values are initialized around, before training.
+    pub initial_approximation_value: RatingValue,
+    pub initialization: FeatureInitialization,
+    /// Spread of the initial feature values around `initial_approximation_value` (unused for `Constant`).
+    pub initialization_scale: RatingValue,
+    /// Whether every SGD epoch visits the ratings in a new random order.
+    pub shuffle: bool,
+    /// Seed of all randomness during training. Two trainings with the same seed
+    /// and configuration on the same ratings produce identical models.
+    pub seed: u64,
+    /// Amount of SGD steps that a rating ingested by `ingest_rating()` is applied to the model with.
+    pub online_steps: usize,
+    /// Whether ingested ratings also update the anime's features and bias, instead of only the user's.
+    pub online_update_animes: bool,
+    /// Minimum rating for an interaction to count as positive feedback for `TrainerKind::Bpr`.
+    pub positive_threshold: RatingValue,
+    /// Similarity between animes that `TrainerKind::ItemKnn` is based on.
+    pub neighborhood_similarity: NeighborhoodSimilarity,
+    /// Amount of most similar animes that `TrainerKind::ItemKnn` keeps per anime.
+    pub neighborhood_size: usize,
+    /// Similarities of animes with `n` common raters are multiplied by `n / (n + neighborhood_shrinkage)`.
+    pub neighborhood_shrinkage: RatingValue,
+    /// Strategies that are tried in order for users that are unknown to the model.
+    /// The first one that has recommendations wins. Empty disables fallback recommendations.
+    pub fallback: Vec<FallbackStrategy>,
+    /// Maximum amount of fallback recommendations.
+    pub fallback_count: usize,
+    /// Damping constant for the anime averages and user offsets. This acts as `k`
+    /// virtual ratings with the global average, which pulls the averages of animes
+    /// and users with only a few ratings towards the global average.
+    pub k: RatingValue
+}
+impl Default for RecommendationEngineConf {
+    fn default() -> Self {
+        return Self {
+            trainer: TrainerKind::FunkSvd,
+            features: 25,
+            feature_labels: Vec::new(),
+            learn_rate: 0.01,
+            threads: 1,
+            min_steps: 25,
+            max_steps: 120,
+            min_improvement: 0.00001,
+            validation_fraction: 0.0,
+            regularization_parameter: 0.02,
+            bias_regularization_parameter: 0.005,
+            prediction_sanitizer: PREDICTION_SANITIZER_CLAMP,
+            rating_scale: RatingScale::default(),
+            round_predictions: false,
+            similarity: SimilarityMetric::Euclidean,
+            neighbor_index: NeighborIndexKind::KdTree,
+            initial_approximation_value: 0.1,
+            initialization: FeatureInitialization::Constant,
+            initialization_scale: 0.05,
+            shuffle: false,
+            seed: 0,
+            online_steps: 10,
+            online_update_animes: false,
+            positive_threshold: RatingScale::default().good_rating(),
+            neighborhood_similarity: NeighborhoodSimilarity::AdjustedCosine,
+            neighborhood_size: 50,
+            neighborhood_shrinkage: 100.0,
+            fallback: Vec::new(),
+            fallback_count: 20,
+            k: 25.0
+        };
+    }
+}
+
+
+/// Struct that contains the RecommendationEngine's internal
+/// (thread-safe) state. This contains the data that is used to
+/// make recommendations. After the engine is done re-training
+/// the state will be locked and swapped with the new one.
+struct RecommendationEngineState {
+    // General rating statistics
+    global_rating_avg: RatingValue,
+    global_avg_offset: RatingValue,
+
+    // Anime-specific rating statistics
+    anime_rating_cnt: na::DVector<usize>,
+    anime_rating_avg: na::DVector<RatingValue>,
+
+    // User-specific rating statistics
+    user_rating_cnt: na::DVector<usize>,
+    user_avg_offset: na::DVector<RatingValue>,
+
+    // Baseline prediction that the feature matrices are trained on top of:
+    // global_bias + anime_bias[anime] + user_bias[user]. Trainers that don't learn
+    // biases use the damped anime averages and user offsets from above.
+    global_bias: RatingValue,
+    anime_bias: na::DVector<RatingValue>,
+    user_bias: na::DVector<RatingValue>,
+
+    // Feature matrices that are calculated using the funkSVD.
+    // These are the matrices used to make a personalized prediction.
+    anime_features: na::DMatrix<RatingValue>,
+    user_features: na::DMatrix<RatingValue>,
+
+    // Only trained by SVD++: implicit feedback features per anime (animes x features) and
+    // per user their normalized sum over all animes the user rated (features x users).
+    implicit_anime_features: Option<na::DMatrix<RatingValue>>,
+    implicit_user_features: Option<na::DMatrix<RatingValue>>,
+
+    // Only trained by item-kNN: the most similar animes per anime, and the ratings per user.
+    item_neighborhood: Option<ItemNeighborhood>,
+
+    // Spatial indices of the feature-vectors within the feature matrices
+    // above. These are used for finding similar user2user and anime2anime.
+    anime_feature_index: SimilarityIndex,
+    user_feature_index: SimilarityIndex,
+
+    // model statistics
+    ratings: RatingContainer,
+    // Ratings that are held out of the training, to decide when to stop it
+    validation: Vec<Rating>,
+    approximation_error: RatingValue,
+    training_curve: TrainingCurve
+}
+impl RecommendationEngineState {
+    pub fn new(ratings: RatingContainer) -> Self {
+        return Self {
+            global_rating_avg: 0.0, global_avg_offset: 0.0,
+            anime_rating_cnt: na::DVector::from_element(0,0), anime_rating_avg: na::DVector::from_element(0,0.0),
+            user_rating_cnt: na::DVector::from_element(0,0), user_avg_offset: na::DVector::from_element(0,0.0),
+            global_bias: 0.0, anime_bias: na::DVector::from_element(0,0.0), user_bias: na::DVector::from_element(0,0.0),
+            anime_features: na::DMatrix::from_element(0,0,0.0), user_features: na::DMatrix::from_element(0,0,0.0),
+            implicit_anime_features: None, implicit_user_features: None,
+            item_neighborhood: None,
+            anime_feature_index: SimilarityIndex::empty(SimilarityMetric::Euclidean),
+            user_feature_index: SimilarityIndex::empty(SimilarityMetric::Euclidean),
+            validation: Vec::new(), approximation_error: 0.0, ratings,
+            training_curve: TrainingCurve { points: Vec::new() }
+        };
+    }
+
+    /// Move a random fraction of the ratings out of the training ratings, into the validation ratings.
+    fn split_validation(&mut self, fraction: RatingValue, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let ratings = std::mem::replace(&mut self.ratings.ratings, Vec::new());
+        let (validation, training) = ratings.into_iter().partition(|_| rng.gen::<RatingValue>() < fraction);
+        self.ratings.ratings = training;
+        self.ratings.rebuild_rated();
+        self.validation = validation;
+    }
+
+    /// Move the validation ratings back into the training ratings, keeping them sorted by anime, then by user.
+    fn restore_validation(&mut self) {
+        self.ratings.ratings.append(&mut self.validation);
+        self.ratings.ratings.sort_unstable_by_key(|r| (r.animeidx, r.useridx));
+        self.ratings.rebuild_rated();
+    }
+
+    /// Baseline prediction for the given anime / user, without the latent features.
+    fn baseline(&self, animeidx: Index, useridx: Index) -> RatingValue {
+        return self.global_bias + self.anime_bias[animeidx] + self.user_bias[useridx];
+    }
+
+    /// Feature vector that represents the taste of the given user. For SVD++
+    /// models, this includes the implicit feedback of which animes the user rated.
+    fn user_vector(&self, useridx: Index) -> na::DVector<RatingValue> {
+        let explicit = self.user_features.column(useridx);
+        return match &self.implicit_user_features {
+            Some(implicit) => explicit + implicit.column(useridx),
+            None => explicit.into_owned()
+        };
+    }
+
+    /// Build spatial indices over the anime/user features for a fast knn neighbor search.
+    /// Item-kNN models have no features, and find similar animes in their neighborhoods instead.
+    fn build_spatial_indices(&mut self, conf: &RecommendationEngineConf) {
+        if self.item_neighborhood.is_some() {
+            return;
+        }
+        self.anime_feature_index = SimilarityIndex::build(conf.similarity, conf.neighbor_index, conf.seed, &self.anime_vectors());
+        self.user_feature_index = SimilarityIndex::build(conf.similarity, conf.neighbor_index, conf.seed, &self.user_vectors());
+    }
+
+    /// Similarity of two animes in [0, 1]. Item-kNN models use their neighborhood
+    /// similarities, where animes that are not each other's neighbors have a similarity of 0.
+    fn anime_similarity(&self, metric: SimilarityMetric, a0: Index, a1: Index) -> RatingValue {
+        return match &self.item_neighborhood {
+            Some(neighborhood) => {
+                let find = |a: Index, other: Index| neighborhood.neighbors[a].iter().find(|&&(n, _)| n == other).map(|&(_, s)| s);
+                find(a0, a1).or_else(|| find(a1, a0)).unwrap_or(0.0)
+            },
+            None => metric.similarity(&self.anime_features.row(a0).transpose(), &self.anime_features.row(a1).transpose())
+        };
+    }
+
+    fn anime_vectors(&self) -> Vec<na::DVector<RatingValue>> {
+        return (0..self.anime_features.nrows()).map(|a| self.anime_features.row(a).transpose()).collect();
+    }
+
+    fn user_vectors(&self) -> Vec<na::DVector<RatingValue>> {
+        return (0..self.user_features.ncols()).map(|u| self.user_vector(u)).collect();
+    }
+
+    fn to_snapshot(&self, trainer: TrainerKind) -> EngineSnapshot {
+        return EngineSnapshot {
+            version: SNAPSHOT_VERSION,
+            created_at: EngineSnapshot::now(),
+            trainer,
+            global_rating_avg: self.global_rating_avg,
+            global_avg_offset: self.global_avg_offset,
+            anime_rating_cnt: self.anime_rating_cnt.as_slice().to_vec(),
+            anime_rating_avg: self.anime_rating_avg.as_slice().to_vec(),
+            user_rating_cnt: self.user_rating_cnt.as_slice().to_vec(),
+            user_avg_offset: self.user_avg_offset.as_slice().to_vec(),
+            global_bias: self.global_bias,
+            anime_bias: self.anime_bias.as_slice().to_vec(),
+            user_bias: self.user_bias.as_slice().to_vec(),
+            anime_features: MatrixSnapshot::from_matrix(&self.anime_features),
+            user_features: MatrixSnapshot::from_matrix(&self.user_features),
+            implicit_anime_features: self.implicit_anime_features.as_ref().map(MatrixSnapshot::from_matrix),
+            implicit_user_features: self.implicit_user_features.as_ref().map(MatrixSnapshot::from_matrix),
+            item_neighbors: self.item_neighborhood.as_ref().map(|n| n.neighbors.clone()),
+            anime_ids: self.ratings.animes.iter().map(|a| a.id).collect(),
+            user_ids: self.ratings.users.iter().map(|u| u.id).collect(),
+            rated: self.ratings.rated.clone(),
+            approximation_error: self.approximation_error
+        };
+    }
+
+    fn from_snapshot(snapshot: EngineSnapshot, conf: &RecommendationEngineConf) -> Result<Self, SnapshotError> {
+        // Whether the model predicts ratings or ranking scores depends on the trainer
+        if snapshot.trainer != conf.trainer {
+            return Err(SnapshotError::Inconsistent);
+        }
+        let (anime_cnt, user_cnt) = (snapshot.anime_ids.len(), snapshot.user_ids.len());
+        let (af, uf) = (&snapshot.anime_features, &snapshot.user_features);
+        if snapshot.anime_rating_cnt.len() != anime_cnt || snapshot.anime_rating_avg.len() != anime_cnt
+                || snapshot.user_rating_cnt.len() != user_cnt || snapshot.user_avg_offset.len() != user_cnt
+                || snapshot.anime_bias.len() != anime_cnt || snapshot.user_bias.len() != user_cnt
+                || af.nrows != anime_cnt || uf.ncols != user_cnt || af.ncols != uf.nrows
+                || !af.is_consistent() || !uf.is_consistent() {
+            return Err(SnapshotError::Inconsistent);
+        }
+        let implicit_shapes_valid = match (&snapshot.implicit_anime_features, &snapshot.implicit_user_features) {
+            (Some(ia), Some(iu)) => ia.is_consistent() && iu.is_consistent()
+                                    && ia.nrows == af.nrows && ia.ncols == af.ncols && iu.nrows == uf.nrows && iu.ncols == uf.ncols,
+            (None, None) => true,
+            _ => false
+        };
+        if !implicit_shapes_valid {
+            return Err(SnapshotError::Inconsistent);
+        }
+        let neighborhood_valid = match &snapshot.item_neighbors {
+            Some(neighbors) => neighbors.len() == anime_cnt && neighbors.iter().flatten().all(|&(a, _)| a < anime_cnt),
+            None => true
+        };
+        if !neighborhood_valid || snapshot.rated.len() != user_cnt
+                || snapshot.rated.iter().flatten().any(|&(a, _)| a >= anime_cnt) {
+            return Err(SnapshotError::Inconsistent);
+        }
+
+        let mut state = Self::new(RatingContainer::from_ids(snapshot.anime_ids, snapshot.user_ids));
+        state.ratings.rated = snapshot.rated;
+        state.global_rating_avg = snapshot.global_rating_avg;
+        state.global_avg_offset = snapshot.global_avg_offset;
+        state.anime_rating_cnt = na::DVector::from_vec(snapshot.anime_rating_cnt);
+        state.anime_rating_avg = na::DVector::from_vec(snapshot.anime_rating_avg);
+        state.user_rating_cnt = na::DVector::from_vec(snapshot.user_rating_cnt);
+        state.user_avg_offset = na::DVector::from_vec(snapshot.user_avg_offset);
+        state.global_bias = snapshot.global_bias;
+        state.anime_bias = na::DVector::from_vec(snapshot.anime_bias);
+        state.user_bias = na::DVector::from_vec(snapshot.user_bias);
+        state.anime_features = snapshot.anime_features.into_matrix();
+        state.user_features = snapshot.user_features.into_matrix();
+        state.implicit_anime_features = snapshot.implicit_anime_features.map(MatrixSnapshot::into_matrix);
+        state.implicit_user_features = snapshot.implicit_user_features.map(MatrixSnapshot::into_matrix);
+        state.item_neighborhood = snapshot.item_neighbors.map(|neighbors| ItemNeighborhood { neighbors });
+        state.approximation_error = snapshot.approximation_error;
+        state.build_spatial_indices(conf);
+        return Ok(state);
+    }
+}
+
+
+/// This is beautiful RecoChan. It's a latent factor analysis based
+/// recommendation engine. This is essentially the method that made
+/// 3rd place in the Netflix-Challenge in 2006.
+pub struct RecommendationEngine {
+    config: RecommendationEngineConf,
+
+    rating_provider: Box<dyn RatingDataProvider + Send + Sync>,
+    snapshot_path: Option<PathBuf>,
+    training_curve_path: Option<PathBuf>,
+    // Ratings that were ingested since the last training, as (animeid, userid, rating)
+    ingested_ratings: Mutex<Vec<(Id, Id, RatingValue)>>,
+    // Animes of recently ingested ratings, for the trending fallback
+    recent_ratings: Mutex<RecentRatings>,
+    state: RwLock<Option<RecommendationEngineState>>
+}
+impl RecommendationEngine {
+    pub fn new(config: RecommendationEngineConf, rating_provider: Box<dyn RatingDataProvider + Send + Sync>) -> Self {
+        let recent_ratings = RecentRatings::new(&config.fallback);
+        return Self {
+            config, rating_provider: rating_provider,
+            snapshot_path: None,
+            training_curve_path: None,
+            ingested_ratings: Mutex::new(Vec::new()),
+            recent_ratings: Mutex::new(recent_ratings),
+            state: RwLock::new(None)
+        };
+    }
+
+    /// Let the engine write a snapshot of its model to the given path after
+    /// every successful call to `retrain()`.
+    pub fn with_snapshot_path(mut self, path: &str) -> Self {
+        self.snapshot_path = Some(PathBuf::from(path));
+        return self;
+    }
+
+    /// Let the engine write the training curve to the given path after every call to `retrain()`.
+    /// Paths ending in `.csv` are written as CSV, all others as JSON.
+    pub fn with_training_curve_path(mut self, path: &str) -> Self {
+        self.training_curve_path = Some(PathBuf::from(path));
+        return self;
+    }
+
+    /// Load a previously written snapshot and use it as the active model.
+    /// Returns the age of the loaded snapshot in seconds.
+    pub fn load_snapshot(&self, path: &Path) -> Result<u64, SnapshotError> {
+        let snapshot = EngineSnapshot::load(path)?;
+        let age = snapshot.age();
+        self.restore_snapshot(snapshot)?;
+        return Ok(age);
+    }
+
+    /// Use an already loaded snapshot (see `EngineSnapshot::load`) as the active model.
+    pub fn restore_snapshot(&self, snapshot: EngineSnapshot) -> Result<(), SnapshotError> {
+        let state = RecommendationEngineState::from_snapshot(snapshot, &self.config)?;
+        *self.state.write().unwrap() = Some(state);
+        return Ok(());
+    }
+
+    fn save_snapshot(&self, path: &Path) -> Result<(), SnapshotError> {
+        let snapshot = match self.state.read().unwrap().as_ref() {
+            Some(state) => state.to_snapshot(self.config.trainer),
+            None => return Ok(())
+        };
+        return snapshot.save(path);
+    }
+
+    /// This method will acquire the current list of ratings from the configured
+    /// data-source, use that to train a new model and then swap the current model with the
+    /// newly trained one. If the ratings can not be acquired, the current model is kept.
+    pub fn retrain(&self) -> Result<(), ProviderError> {
+        info!(target: "RecommendationEngine", "Start training...");
+        let merged_ratings = self.ingested_ratings.lock().unwrap().clone();
+        let rating_data = match merged_ratings.len() {
+            0 => self.rating_provider.get()?,
+            _ => self.rating_provider.get()?.merge(&merged_ratings)
+        };
+        let mut state = RecommendationEngineState::new(rating_data);
+        let trainer: Box<dyn Trainer> = match self.config.trainer {
+            TrainerKind::FunkSvd => Box::new(FunkSvdTrainer),
+            TrainerKind::Als => Box::new(AlsTrainer),
+            TrainerKind::BiasedSgd => Box::new(BiasedSgdTrainer),
+            TrainerKind::SvdPlusPlus => Box::new(SvdPlusPlusTrainer),
+            TrainerKind::ItemKnn => Box::new(ItemKnnTrainer),
+            TrainerKind::Bpr => Box::new(BprTrainer)
+        };
+        let mut progress = TrainingProgress::new(&self.config);
+        if self.config.validation_fraction > 0.0 {
+            state.split_validation(self.config.validation_fraction, self.config.seed);
+            info!(target: "RecommendationEngine", "Holding out {} ratings for validation", state.validation.len());
+            self.init_statistics(&mut state);
+            trainer.train(&self.config, &mut state, &mut progress);
+
+            // The held-out ratings only decided when to stop. Train the model on all ratings, with the same amount of epochs.
+            info!(target: "RecommendationEngine", "Retraining on all ratings with the epochs picked on the validation ratings");
+            state.restore_validation();
+            self.init_statistics(&mut state);
+            trainer.train(&self.config, &mut state, &mut TrainingProgress::with_epochs(&self.config, progress.epochs()));
+        } else {
+            self.init_statistics(&mut state);
+            trainer.train(&self.config, &mut state, &mut progress);
+        }
+        state.training_curve = progress.finish();
+        if let Some(curve_path) = &self.training_curve_path {
+            let curve = match curve_path.extension().and_then(|e| e.to_str()) {
+                Some("csv") => state.training_curve.to_csv(),
+                _ => state.training_curve.to_json()
+            };
+            if let Err(e) = fs::write(curve_path, curve) {
+                error!(target: "RecommendationEngine", "Failed to write training curve to {}: {}", curve_path.display(), e);
+            }
+        }
+
+        info!(target: "RecommendationEngine", "Building spatial indices for anime- / user-features");
+        state.build_spatial_indices(&self.config);
+
+        // Swap newly trained state with the state that is currently used for predictions
+        // Unwrap is ok here. Can only fail if a writer panics - which will not happen.
+        info!(target: "RecommendationEngine", "Finished training - swapping with active EngineState");
+        info!(target: "RecommendationEngine", "Average Prediction Error: {:.3} Stars", state.approximation_error);
+        {
+            // Ratings that were ingested during the training are not part of the new model yet.
+            // The ingested ratings stay locked until the swap, so none of them gets lost or applied twice.
+            let mut ingested = self.ingested_ratings.lock().unwrap();
+            ingested.drain(..merged_ratings.len());
+            for &(animeid, userid, rating) in ingested.iter() {
+                self.apply_rating(&mut state, animeid, userid, rating);
+            }
+            *self.state.write().unwrap() = Some(state);
+        }
+
+        if let Some(snapshot_path) = &self.snapshot_path {
+            match self.save_snapshot(snapshot_path) {
+                Ok(_) => info!(target: "RecommendationEngine", "Wrote model snapshot to {}", snapshot_path.display()),
+                Err(e) => error!(target: "RecommendationEngine", "Failed to write model snapshot to {}: {}", snapshot_path.display(), e)
+            }
+        }
+        return Ok(());
+    }
+
+    fn init_statistics(&self, state: &mut RecommendationEngineState) {
+        let (ratings, animes, users) = (&state.ratings.ratings, &state.ratings.animes, &state.ratings.users);
+        let (global_rating_avg, global_avg_offset, k) = (&mut state.global_rating_avg, &mut state.global_avg_offset, self.config.k);
+        let (anime_rating_cnt, anime_rating_avg) = (&mut state.anime_rating_cnt, &mut state.anime_rating_avg);
+        let (user_rating_cnt, user_avg_offset) = (&mut state.user_rating_cnt, &mut state.user_avg_offset);
+
+        // Start from scratch, init_statistics() runs again when refitting on the validation ratings
+        *global_rating_avg = 0.0;
+        *global_avg_offset = 0.0;
+        *anime_rating_cnt = na::DVector::from_element(animes.len(), 0);
+        *anime_rating_avg = na::DVector::from_element(animes.len(), 0.0);
+        *user_rating_cnt = na::DVector::from_element(users.len(), 0);
+        *user_avg_offset = na::DVector::from_element(users.len(), 0.0);
+
+        // Calculate global rating average
+        // Calculate average rating per anime
+        for rating in ratings {
+            anime_rating_cnt[rating.animeidx] += 1;
+            anime_rating_avg[rating.animeidx] += rating.rating;
+            *global_rating_avg += rating.rating;
+        }
+        *global_rating_avg /= ratings.len() as RatingValue;
+        for idx in 0..animes.len() {
+            anime_rating_avg[idx] = (*global_rating_avg * k + anime_rating_avg[idx]) / (k + anime_rating_cnt[idx] as RatingValue);
+        }
+
+        // Calculate average user rating-offset
+        for rating in ratings {
+            user_rating_cnt[rating.useridx] += 1;
+            user_avg_offset[rating.useridx] += rating.rating - anime_rating_avg[rating.animeidx];
+            *global_avg_offset += rating.rating - anime_rating_avg[rating.animeidx];
+        }
+        *global_avg_offset /= ratings.len() as RatingValue;
+        for idx in 0..users.len() {
+            user_avg_offset[idx] = (*global_avg_offset * k + user_avg_offset[idx]) / (k + user_rating_cnt[idx] as RatingValue);
+        }
+
+        // Use the damped averages as baseline, until a trainer learns a better one
+        state.global_bias = 0.0;
+        state.anime_bias = state.anime_rating_avg.clone();
+        state.user_bias = state.user_avg_offset.clone();
+    }
+
+    /// Range of the ratings, that this engine is trained on.
+    pub fn rating_scale(&self) -> &RatingScale {
+        return &self.config.rating_scale;
+    }
+
+    /// Record a rating that was made after the last training. The rating is merged into the ratings
+    /// of the next `retrain()`. If both the user and the anime are part of the current model, the rating
+    /// is also applied to it right away. Returns whether the current model was updated.
+    pub fn ingest_rating(&self, userid: Id, animeid: Id, rating: RatingValue) -> Result<bool, PredictionError> {
+        self.recent_ratings.lock().map_err(|_| PredictionError::Unknown)?.push(EngineSnapshot::now(), animeid);
+        let mut ingested = self.ingested_ratings.lock().map_err(|_| PredictionError::Unknown)?;
+        ingested.push((animeid, userid, rating));
+        let mut state_lock = self.state.write().map_err(|_| PredictionError::Unknown)?;
+        return Ok(match state_lock.as_mut() {
+            Some(state) => self.apply_rating(state, animeid, userid, rating),
+            None => false
+        });
+    }
+
+    /// Incrementally update the model with a single rating, by adding it to the rating statistics and
+    /// doing a few SGD steps on the user's features and bias (and optionally the anime's).
+    /// This only touches one column / row, so the write lock on the state is held very briefly.
+    /// The spatial indices are not updated, similar users / animes only change with the next training.
+    /// Ranking models are not trained on rating values, so they only pick ratings up with the next training.
+    fn apply_rating(&self, state: &mut RecommendationEngineState, animeid: Id, userid: Id, rating: RatingValue) -> bool {
+        let (a, u) = match (state.ratings.anime2row(animeid), state.ratings.user2column(userid)) {
+            (Some(a), Some(u)) => (a, u),
+            _ => return false
+        };
+        let previous_rating = state.ratings.rated[u].binary_search_by_key(&a, |&(a, _)| a).ok().map(|pos| state.ratings.rated[u][pos].1);
+        state.ratings.set_rated(u, a, rating);
+        if !self.config.trainer.predicts_ratings() {
+            return false;
+        }
+        let conf = &self.config;
+
+        // Add the rating to the damped averages, as if it had been there in init_statistics()
+        let (anime_cnt, user_cnt) = (state.anime_rating_cnt[a] as RatingValue, state.user_rating_cnt[u] as RatingValue);
+        match previous_rating {
+            // A re-rating replaces the contribution of the previous rating, the counts stay the same
+            Some(previous_rating) => {
+                state.anime_rating_avg[a] += (rating - previous_rating) / (conf.k + anime_cnt);
+                state.user_avg_offset[u] += (rating - previous_rating) / (conf.k + user_cnt);
+            },
+            None => {
+                state.anime_rating_avg[a] += (rating - state.anime_rating_avg[a]) / (conf.k + anime_cnt + 1.0);
+                state.anime_rating_cnt[a] += 1;
+                state.user_avg_offset[u] += (rating - state.anime_rating_avg[a] - state.user_avg_offset[u]) / (conf.k + user_cnt + 1.0);
+                state.user_rating_cnt[u] += 1;
+            }
+        }
+
+        for _ in 0..conf.online_steps {
+            let user_vector = state.user_vector(u);
+            let prediction = match &state.item_neighborhood {
+                Some(neighborhood) => neighborhood.predict_one(state, &state.ratings.rated[u], state.user_bias[u], a),
+                None => state.baseline(a, u) + state.anime_features.row(a).transpose().dot(&user_vector)
+            };
+            let err = rating - prediction;
+
+            state.user_bias[u] += conf.learn_rate * (err - conf.bias_regularization_parameter * state.user_bias[u]);
+            if conf.online_update_animes {
+                state.anime_bias[a] += conf.learn_rate * (err - conf.bias_regularization_parameter * state.anime_bias[a]);
+            }
+            for f in 0..state.anime_features.ncols() {
+                let af = state.anime_features[(a,f)];
+                let uf = state.user_features[(f,u)];
+                state.user_features[(f,u)] += conf.learn_rate * (err * af - conf.regularization_parameter * uf);
+                if conf.online_update_animes {
+                    state.anime_features[(a,f)] += conf.learn_rate * (err * user_vector[f] - conf.regularization_parameter * af);
+                }
+            }
+        }
+        return true;
+    }
+
+    fn use_state<F,T>(&self, cb: F) -> Result<T, PredictionError>
+                where F: FnOnce(&RecommendationEngineState) -> Result<T, PredictionError> {
+        let state_lock = self.state.read().map_err(|_| PredictionError::Unknown)?;
+        let state = state_lock.as_ref().ok_or(PredictionError::NotInitialized)?;
+        return cb(state);
+    }
+
+    /// Errors per epoch of the training of the current model. Empty for models loaded from a snapshot.
+    pub fn training_curve(&self) -> Result<TrainingCurve, PredictionError> {
+        return self.use_state(|state| Ok(state.training_curve.clone()));
+    }
+
+    /// Predicted ratings of all animes for the given user, best first. Animes the user already rated are
+    /// left out, unless `include_rated` is set - then they are included together with the user's rating.
+    /// Only the `count` (all, if None) best predictions after skipping the `offset` best ones are returned.
+    pub fn predict_user_ratings<P>(&self, userid: Id, include_rated: bool, offset: usize, count: Option<usize>, filter: P) -> Result<UserRatingPredictionResult, PredictionError>
+                where P: FnMut(&UserRatingPrediction) -> bool {
+        return self.use_state(|state| {
+            let useridx = state.ratings.user2column(userid).ok_or(PredictionError::UnknownUser)?;
+            let (rated, user_bias) = (&state.ratings.rated[useridx], state.user_bias[useridx]);
+            let predictions = match &state.item_neighborhood {
+                Some(neighborhood) => neighborhood.predict(state, &state.ratings.rated[useridx], user_bias),
+                None => Self::latent_predictions(state, &state.user_vector(useridx), user_bias)
+            };
+            return Ok(self.rank_predictions(state, &predictions, rated, include_rated, offset, count, filter));
+        });
+    }
+
+    /// Attach an explanation of how it came about to every one of the given predictions for the given user.
+    /// Every explanation compares the anime with all animes the user rated, so only the predictions
+    /// that are actually returned should be explained. Animes unknown to the model stay unexplained.
+    pub fn explain_predictions(&self, userid: Id, predictions: &mut [UserRatingPrediction]) -> Result<(), PredictionError> {
+        return self.use_state(|state| {
+            let useridx = state.ratings.user2column(userid).ok_or(PredictionError::UnknownUser)?;
+            let (rated, user_bias, user_vector) = (&state.ratings.rated[useridx], state.user_bias[useridx], state.user_vector(useridx));
+            for prediction in predictions.iter_mut() {
+                let animeidx = match state.ratings.anime2row(prediction.animeid) {
+                    Some(animeidx) => animeidx,
+                    None => continue
+                };
+                let unsanitized = match &state.item_neighborhood {
+                    Some(neighborhood) => neighborhood.predict_one(state, &state.ratings.rated[useridx], user_bias, animeidx),
+                    None => state.baseline(animeidx, useridx) + state.anime_features.row(animeidx).transpose().dot(&user_vector)
+                };
+                prediction.explanation = Some(self.explain(state, animeidx, unsanitized, rated, user_bias));
+            }
+            return Ok(());
+        });
+    }
+
+    /// Amount of the user's animes that an explanation lists as similar to the recommended one.
+    pub const EXPLANATION_ANIMES: usize = 3;
+
+    /// Split the (unsanitized) prediction of the given anime for a user with the given (anime, rating)
+    /// pairs and bias into its parts, and find the animes the user liked that are the most similar to it.
+    fn explain(&self, state: &RecommendationEngineState, animeidx: Index, prediction: RatingValue,
+               rated: &[(Index, RatingValue)], user_bias: RatingValue) -> PredictionExplanation {
+        // Trainers that don't learn biases have the global average within the anime biases
+        let global_average = if self.config.trainer.predicts_ratings() { state.global_rating_avg } else { state.global_bias };
+        let anime_bias = state.global_bias + state.anime_bias[animeidx];
+
+        let rating_avg = rated.iter().map(|&(_, rating)| rating).sum::<RatingValue>() / rated.len().max(1) as RatingValue;
+        let mut similar_rated: Vec<SimilarRatedAnime> = rated.iter()
+                    .filter(|&&(a, rating)| a != animeidx && rating >= rating_avg)
+                    .map(|&(a, rating)| SimilarRatedAnime {
+                        animeid: state.ratings.row2anime(a).unwrap(),
+                        rating,
+                        similarity: state.anime_similarity(self.config.similarity, animeidx, a)
+                    })
+                    .filter(|similar| similar.similarity > 0.0)
+                    .collect();
+        similar_rated.sort_by(|s0, s1| s1.similarity.partial_cmp(&s0.similarity).unwrap_or(Ordering::Equal)
+                    .then(s1.rating.partial_cmp(&s0.rating).unwrap_or(Ordering::Equal)));
+        similar_rated.truncate(Self::EXPLANATION_ANIMES);
+
+        return PredictionExplanation {
+            global_average,
+            anime_bias: anime_bias - global_average,
+            user_offset: user_bias,
+            latent_factors: prediction - anime_bias - user_bias,
+            similar_rated
+        };
+    }
+
+    /// Amount of best predictions per requested recommendation that `diversify()` should choose from.
+    pub const DIVERSITY_CANDIDATE_FACTOR: usize = 10;
+
+    /// Re-rank the given (best first) predictions by maximal marginal relevance and return the best `count`.
+    /// One after another, the prediction with the highest `(1 - diversity) * relevance - diversity * redundancy`
+    /// is selected, where the relevance is the predicted rating scaled into [0, 1] over all given predictions,
+    /// and the redundancy is the highest similarity to any of the already selected animes.
+    /// A `diversity` of 0 keeps the order, 1 only picks animes that are as different as possible.
+    pub fn diversify(&self, predictions: UserRatingPredictionResult, diversity: RatingValue, count: usize) -> Result<UserRatingPredictionResult, PredictionError> {
+        return self.use_state(|state| {
+            let min = predictions.iter().map(|p| p.rating).fold(std::f64::INFINITY, RatingValue::min);
+            let max = predictions.iter().map(|p| p.rating).fold(std::f64::NEG_INFINITY, RatingValue::max);
+            let range = if max > min { max - min } else { 1.0 };
+
+            // (prediction, anime index, redundancy)
+            let mut remaining: Vec<(UserRatingPrediction, Index, RatingValue)> = predictions.into_iter()
+                        .filter_map(|p| state.ratings.anime2row(p.animeid).map(|a| (p, a, 0.0))).collect();
+            let mut selected = Vec::with_capacity(count.min(remaining.len()));
+            while selected.len() < count && !remaining.is_empty() {
+                let score = |&(ref p, _, redundancy): &(UserRatingPrediction, Index, RatingValue)| {
+                    (1.0 - diversity) * (p.rating - min) / range - diversity * redundancy
+                };
+                // On ties, the first (i.e. better rated) prediction wins
+                let mut best = 0;
+                for idx in 1..remaining.len() {
+                    if score(&remaining[idx]) > score(&remaining[best]) { best = idx; }
+                }
+                let (prediction, animeidx, _) = remaining.remove(best);
+                for candidate in remaining.iter_mut() {
+                    candidate.2 = candidate.2.max(state.anime_similarity(self.config.similarity, animeidx, candidate.1));
+                }
+                selected.push(prediction);
+            }
+            return Ok(selected);
+        });
+    }
+
+    /// Average dissimilarity (1 - similarity) of all pairs of the given animes.
+    /// None for less than two animes that are known to the model.
+    pub fn intra_list_diversity(&self, animeids: &[Id]) -> Result<Option<RatingValue>, PredictionError> {
+        return self.use_state(|state| {
+            let animes: Vec<Index> = animeids.iter().filter_map(|&animeid| state.ratings.anime2row(animeid)).collect();
+            let (mut dissimilarity_sum, mut pairs) = (0.0, 0);
+            for (i, &a0) in animes.iter().enumerate() {
+                for &a1 in &animes[i + 1..] {
+                    dissimilarity_sum += 1.0 - state.anime_similarity(self.config.similarity, a0, a1);
+                    pairs += 1;
+                }
+            }
+            return Ok(if pairs > 0 { Some(dissimilarity_sum / pairs as RatingValue) } else { None });
+        });
+    }
+
+    /// Non-personalized recommendations for users that are unknown to the model, from the first
+    /// strategy of the configured fallback chain that has any. None if none of them has any.
+    /// Only `Popular` needs a trained model, the other strategies also work before the first training.
+    pub fn fallback_recommendations(&self) -> Result<Option<FallbackResult>, PredictionError> {
+        let count = self.config.fallback_count;
+        for strategy in &self.config.fallback {
+            let recommendations: Vec<FallbackRecommendation> = match strategy {
+                FallbackStrategy::Popular => {
+                    let popular = self.use_state(|state| {
+                        let mut popular: Vec<(Id, RatingValue)> = state.ratings.animes.iter().enumerate()
+                                    .map(|(idx, a)| (a.id, state.anime_rating_avg[idx])).collect();
+                        popular.sort_by(|p0, p1| p1.1.partial_cmp(&p0.1).unwrap_or(std::cmp::Ordering::Equal));
+                        return Ok(popular.into_iter().take(count).map(|(animeid, avg)| FallbackRecommendation { animeid, score: Some(avg) }).collect());
+                    });
+                    match popular {
+                        // Without a model, there are no averages to rank by. Try the next strategy.
+                        Err(PredictionError::NotInitialized) => Vec::new(),
+                        popular => popular?
+                    }
+                },
+                FallbackStrategy::Trending { window_secs } => {
+                    let recent_ratings = self.recent_ratings.lock().map_err(|_| PredictionError::Unknown)?;
+                    recent_ratings.trending(EngineSnapshot::now(), *window_secs).into_iter().take(count)
+                                .map(|(animeid, cnt)| FallbackRecommendation { animeid, score: Some(cnt as RatingValue) }).collect()
+                },
+                FallbackStrategy::Curated { animes } => {
+                    animes.iter().take(count).map(|&animeid| FallbackRecommendation { animeid, score: None }).collect()
+                }
+            };
+            if !recommendations.is_empty() {
+                return Ok(Some(FallbackResult::fallback(strategy, recommendations)));
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Summary of every latent feature, with the `count` animes that contain it the most and the least.
+    pub fn feature_summaries(&self, count: usize) -> Result<Vec<FeatureSummary>, PredictionError> {
+        return self.use_state(|state| {
+            if state.item_neighborhood.is_some() {
+                return Err(PredictionError::Unsupported);
+            }
+            return Ok(introspection::summarize_features(state, &self.config.feature_labels, count));
+        });
+    }
+
+    /// The `count` latent features that influence the predictions for the given user the most.
+    pub fn dominant_user_features(&self, userid: Id, count: usize) -> Result<Vec<DominantFeature>, PredictionError> {
+        return self.use_state(|state| {
+            if state.item_neighborhood.is_some() {
+                return Err(PredictionError::Unsupported);
+            }
+            let useridx = state.ratings.user2column(userid).ok_or(PredictionError::UnknownUser)?;
+            return Ok(introspection::dominant_features(state, &self.config.feature_labels, &state.user_vector(useridx), count));
+        });
+    }
+
+    pub fn find_k_similar_users(&self, userid: Id, k: usize) -> Result<SimilarUserResult, PredictionError> {
+        return self.use_state(|state| {
+            if state.item_neighborhood.is_some() {
+                return Err(PredictionError::Unsupported);
+            }
+            let useridx = state.ratings.user2column(userid).ok_or(PredictionError::UnknownUser)?;
+            return self.find_k_users_near(state, &state.user_vector(useridx), k, Some(useridx));
+        });
+    }
+
+    /// Make recommendations for a user that is not part of the trained model (e.g. because
+    /// they signed up after the last training), from the given (animeid, rating) pairs.
+    /// Animes that are unknown to the model are ignored, the given animes are not recommended.
+    /// Item-kNN models predict from the ratings directly, and don't find similar users.
+    pub fn fold_in_user<P>(&self, ratings: &[(Id, RatingValue)], mut filter: P, k: usize) -> Result<FoldInResult, PredictionError>
+                where P: FnMut(&UserRatingPrediction) -> bool {
+        return self.use_state(|state| {
+            let (predictions, similar_users) = match &state.item_neighborhood {
+                Some(neighborhood) => {
+                    let (mut known_ratings, user_bias) = self.fold_in_bias(state, ratings)?;
+                    known_ratings.sort_by_key(|&(a, _)| a);
+                    (neighborhood.predict(state, &known_ratings, user_bias), Vec::new())
+                },
+                None => {
+                    let (user_vector, user_bias) = self.fold_in(state, ratings)?;
+                    (Self::latent_predictions(state, &user_vector, user_bias), self.find_k_users_near(state, &user_vector, k, None)?)
+                }
+            };
+            let recommendations = self.rank_predictions(state, &predictions, &[], false, 0, None, |p| {
+                !ratings.iter().any(|&(animeid, _)| animeid == p.animeid) && filter(p)
+            });
+            return Ok(FoldInResult { recommendations, similar_users });
+        });
+    }
+
+    /// Calculate the feature vector and bias of a user from their ratings, while keeping the anime
+    /// features fixed. This is a small regularized least-squares problem, just like a single ALS step.
+    fn fold_in(&self, state: &RecommendationEngineState, ratings: &[(Id, RatingValue)]) -> Result<(na::DVector<RatingValue>, RatingValue), PredictionError> {
+        let (known_ratings, user_bias) = self.fold_in_bias(state, ratings)?;
+        let (features, rating_cnt) = (state.anime_features.ncols(), known_ratings.len() as RatingValue);
+
+        let implicit = match &state.implicit_anime_features {
+            Some(implicit_anime_features) => {
+                let mut sum = na::DVector::from_element(features, 0.0);
+                for &(a, _) in &known_ratings {
+                    sum += implicit_anime_features.row(a).transpose();
+                }
+                sum / rating_cnt.sqrt()
+            },
+            None => na::DVector::from_element(features, 0.0)
+        };
+
+        let mut lhs = na::DMatrix::from_element(features, features, 0.0);
+        let mut rhs = na::DVector::from_element(features, 0.0);
+        for &(a, rating) in &known_ratings {
+            let anime_vector = state.anime_features.row(a).transpose();
+            let residual = rating - state.global_bias - state.anime_bias[a] - user_bias - anime_vector.dot(&implicit);
+            lhs += &anime_vector * anime_vector.transpose();
+            rhs += &anime_vector * residual;
+        }
+        for f in 0..features {
+            lhs[(f,f)] += self.config.regularization_parameter * rating_cnt;
+        }
+        let explicit = lhs.cholesky().map(|cholesky| cholesky.solve(&rhs)).ok_or(PredictionError::Unknown)?;
+        return Ok((explicit + implicit, user_bias));
+    }
+
+    /// The (anime index, rating) pairs of the given ratings whose anime is known to the model, and the
+    /// user's bias. The bias is damped towards the average bias of all users, just like for trained users.
+    fn fold_in_bias(&self, state: &RecommendationEngineState, ratings: &[(Id, RatingValue)]) -> Result<(Vec<(Index, RatingValue)>, RatingValue), PredictionError> {
+        let known_ratings: Vec<(Index, RatingValue)> = ratings.iter()
+                .filter_map(|&(animeid, rating)| state.ratings.anime2row(animeid).map(|a| (a, rating)))
+                .collect();
+        if known_ratings.is_empty() {
+            return Err(PredictionError::UnknownAnime);
+        }
+        let k = self.config.k;
+        let avg_user_bias = state.user_bias.iter().sum::<RatingValue>() / state.user_bias.len() as RatingValue;
+        let offset_sum: RatingValue = known_ratings.iter().map(|&(a, rating)| rating - state.global_bias - state.anime_bias[a]).sum();
+        let user_bias = (avg_user_bias * k + offset_sum) / (k + known_ratings.len() as RatingValue);
+        return Ok((known_ratings, user_bias));
+    }
+
+    /// Predictions of the latent factor model for every known anime, for the given user.
+    fn latent_predictions(state: &RecommendationEngineState, user_vector: &na::DVector<RatingValue>, user_bias: RatingValue) -> na::DVector<RatingValue> {
+        return &state.anime_features * user_vector + &state.anime_bias.add_scalar(state.global_bias + user_bias);
+    }
+
+    /// Turn the predictions for every anime into a sorted result. `rated` are the (anime, rating)
+    /// pairs of the animes the user rated, which are left out unless `include_rated` is set.
+    /// With a `count`, only the best `offset + count` predictions are selected with a heap,
+    /// instead of sorting the whole catalog.
+    fn rank_predictions<P>(&self, state: &RecommendationEngineState, predictions: &na::DVector<RatingValue>,
+                           rated: &[(Index, RatingValue)], include_rated: bool, offset: usize, count: Option<usize>, filter: P) -> UserRatingPredictionResult
+                where P: FnMut(&UserRatingPrediction) -> bool {
+        // Ranking scores are not in the rating range, so they must not be clamped into it
+        let (scale, ranking) = (&self.config.rating_scale, !self.config.trainer.predicts_ratings());
+        let sanitize = |prediction: RatingValue| {
+            if ranking { return prediction; }
+            let rating = (self.config.prediction_sanitizer)(prediction, scale);
+            return if self.config.round_predictions { scale.round(rating) } else { rating };
+        };
+        let mut user_ratings: Vec<Option<RatingValue>> = vec![None; predictions.len()];
+        for &(a, rating) in rated {
+            user_ratings[a] = Some(rating);
+        }
+        let candidates = state.ratings.animes.iter().enumerate()
+                                            .filter(|&(idx, _)| include_rated || user_ratings[idx].is_none())
+                                            .map(|(idx, a)| UserRatingPrediction {
+                                                animeid: a.id,
+                                                rating: sanitize(predictions[idx]),
+                                                rated: user_ratings[idx],
+                                                explanation: None
+                                            })
+                                            .filter(filter)
+                                            .map(RankedPrediction);
+
+        // Sort predicted ratings (descending)
+        let ranked: Vec<Reverse<RankedPrediction>> = match count {
+            Some(count) => {
+                // Min-heap of the best predictions so far, whose worst one is dropped when it gets too large
+                let limit = offset.saturating_add(count);
+                let mut best: BinaryHeap<Reverse<RankedPrediction>> = BinaryHeap::with_capacity(limit.min(predictions.len()) + 1);
+                for prediction in candidates {
+                    best.push(Reverse(prediction));
+                    if best.len() > limit { best.pop(); }
+                }
+                best.into_sorted_vec()
+            },
+            None => {
+                let mut all: Vec<Reverse<RankedPrediction>> = candidates.map(Reverse).collect();
+                all.sort();
+                all
+            }
+        };
+        return ranked.into_iter().skip(offset).map(|Reverse(RankedPrediction(prediction))| prediction).collect();
+    }
+
+    fn find_k_users_near(&self, state: &RecommendationEngineState, user_vector: &na::DVector<RatingValue>, k: usize, useridx: Option<Index>) -> Result<SimilarUserResult, PredictionError> {
+        // Use the spatial index to search for users near ours in user-feature-space.
+        // We request one more because the search will also return our user itself
+        let similar_users = state.user_feature_index.nearest(user_vector, k + 1).ok_or(PredictionError::Unknown)?;
+
+        return Ok(similar_users.into_iter().filter(|&other_idx| useridx != Some(other_idx)).take(k).map(|other_idx| SimilarUser {
+            userid: state.ratings.column2user(other_idx).unwrap(),
+            similarity: self.config.similarity.similarity(user_vector, &state.user_vector(other_idx))
+        }).collect());
+    }
+
+    /// Compare the given kinds of indices on the feature vectors of the current model.
+    pub fn benchmark_neighbor_indices(&self, kinds: &[NeighborIndexKind], k: usize, queries: usize) -> Result<Vec<IndexBenchmark>, PredictionError> {
+        return self.use_state(|state| {
+            let (metric, seed) = (self.config.similarity, self.config.seed);
+            let mut results = similarity::benchmark("animes", metric, seed, &state.anime_vectors(), kinds, k, queries);
+            results.extend(similarity::benchmark("users", metric, seed, &state.user_vectors(), kinds, k, queries));
+            return Ok(results);
+        });
+    }
+
+    pub fn find_k_similar_animes(&self, animeid: Id, k: usize) -> Result<SimilarAnimeResult, PredictionError> {
+        return self.use_state(|state| {
+            let animeidx = state.ratings.anime2row(animeid).ok_or(PredictionError::UnknownAnime)?;
+            if let Some(neighborhood) = &state.item_neighborhood {
+                return Ok(neighborhood.neighbors[animeidx].iter().take(k).map(|&(other_idx, similarity)| SimilarAnime {
+                    animeid: state.ratings.row2anime(other_idx).unwrap(),
+                    similarity
+                }).collect());
+            }
+            let anime_vector = state.anime_features.row(animeidx).transpose();
+
+            // Use the spatial index to search for animes near ours in anime-feature-space.
+            // We request one more because the search will also return our anime itself
+            let similar_animes = state.anime_feature_index.nearest(&anime_vector, k + 1).ok_or(PredictionError::Unknown)?;
+
+            return Ok(similar_animes.into_iter().filter(|&other_idx| other_idx != animeidx).take(k).map(|other_idx| SimilarAnime {
+                animeid: state.ratings.row2anime(other_idx).unwrap(),
+                similarity: self.config.similarity.similarity(&anime_vector, &state.anime_features.row(other_idx).transpose())
+            }).collect());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecommendationEngine, RecommendationEngineConf, RecommendationEngineState};
+    use crate::dataprovider::InMemoryDataProvider;
+
+    fn ratings() -> Vec<(u64, u64, f64)> {
+        let mut ratings = Vec::new();
+        for userid in 0..30 {
+            for animeid in 0..20 {
+                if (userid * 7 + animeid * 3) % 5 < 3 {
+                    ratings.push((animeid, userid, ((userid + animeid) % 10) as f64 * 0.5));
+                }
+            }
+        }
+        return ratings;
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn refit_statistics_match_single_call() {
+        let engine = RecommendationEngine::new(RecommendationEngineConf::default(), Box::new(InMemoryDataProvider::new(ratings())));
+
+        let mut single = RecommendationEngineState::new(engine.rating_provider.get().unwrap());
+        engine.init_statistics(&mut single);
+
+        // Same calls as retrain() with a validation split
+        let mut refit = RecommendationEngineState::new(engine.rating_provider.get().unwrap());
+        refit.split_validation(0.2, 0);
+        engine.init_statistics(&mut refit);
+        refit.restore_validation();
+        engine.init_statistics(&mut refit);
+
+        assert_close(single.global_rating_avg, refit.global_rating_avg);
+        assert_close(single.global_avg_offset, refit.global_avg_offset);
+        assert_eq!(single.anime_rating_cnt, refit.anime_rating_cnt);
+        assert_eq!(single.user_rating_cnt, refit.user_rating_cnt);
+        for (a, b) in single.anime_rating_avg.iter().zip(refit.anime_rating_avg.iter()) { assert_close(*a, *b); }
+        for (a, b) in single.user_avg_offset.iter().zip(refit.user_avg_offset.iter()) { assert_close(*a, *b); }
+    }
+}
@@ -0,0 +1,318 @@
+use nalgebra as na;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{any::Any, panic::{self, AssertUnwindSafe}, thread, cell::UnsafeCell, sync::{Arc, Mutex, Condvar, mpsc}};
+use crate::ratings::{Index, RatingValue};
+use super::{RecommendationEngineConf, RecommendationEngineState, shuffle_epoch};
+
+/// Feature values of a single feature that are shared between the training threads.
+struct SharedFeature(Vec<UnsafeCell<RatingValue>>);
+// The stratification in `StratifiedEpoch` guarantees that no two threads ever
+// access the same value concurrently, so no further synchronization is needed.
+unsafe impl Sync for SharedFeature {}
+unsafe impl Send for SharedFeature {}
+impl SharedFeature {
+    fn new<'a, I: Iterator<Item = &'a RatingValue>>(values: I) -> Self {
+        return SharedFeature(values.map(|v| UnsafeCell::new(*v)).collect());
+    }
+    unsafe fn get(&self, idx: Index) -> RatingValue {
+        return *self.0[idx].get();
+    }
+    unsafe fn set(&self, idx: Index, value: RatingValue) {
+        *self.0[idx].get() = value;
+    }
+}
+
+struct BlockRating {
+    animeidx: Index,
+    useridx: Index,
+    rating: RatingValue,
+    residual: RatingValue
+}
+
+/// Barrier that the workers wait on between the strata. Unlike `std::sync::Barrier`, a worker
+/// that panicked can poison it, which releases the other workers instead of leaving them waiting forever.
+struct StratumBarrier {
+    threads: usize,
+    // (waiting workers, generation, poisoned)
+    state: Mutex<(usize, usize, bool)>,
+    released: Condvar
+}
+impl StratumBarrier {
+    fn new(threads: usize) -> Self {
+        return Self { threads, state: Mutex::new((0, 0, false)), released: Condvar::new() };
+    }
+
+    /// Block until all workers reached the barrier. Returns false if it was poisoned.
+    fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let generation = state.1;
+        state.0 += 1;
+        if state.0 == self.threads {
+            state.0 = 0;
+            state.1 += 1;
+            self.released.notify_all();
+        }
+        while state.1 == generation && !state.2 {
+            state = self.released.wait(state).unwrap();
+        }
+        return !state.2;
+    }
+
+    fn poison(&self) {
+        self.state.lock().unwrap().2 = true;
+        self.released.notify_all();
+    }
+}
+
+/// What a worker reports after an epoch. `Err(Some(..))` carries the panic of the worker,
+/// `Err(None)` means that the worker gave up because another one panicked.
+type WorkerResult = Result<(), Option<Box<dyn Any + Send>>>;
+
+/// Runs the SGD epochs of a single feature on multiple threads.
+/// Animes and users are each divided into `threads` groups, which divides the ratings
+/// into `threads x threads` blocks. An epoch is split into `threads` strata, in which every
+/// thread works on one block, such that no two blocks of the same stratum share an anime or
+/// a user. Every rating is visited exactly once per epoch, just like in the serial trainer.
+/// The worker threads are started once per feature, and wait for each other between the strata.
+/// If a worker panics, the others stop and `run` resumes the panic.
+pub struct StratifiedEpoch {
+    threads: usize,
+    anime_feature: Arc<SharedFeature>,
+    user_feature: Arc<SharedFeature>,
+    // Per worker: the seeds of its blocks for the next epoch, by stratum. None stops the worker.
+    epochs: Vec<mpsc::Sender<Option<Vec<u64>>>>,
+    finished: mpsc::Receiver<WorkerResult>,
+    workers: Vec<thread::JoinHandle<()>>
+}
+impl StratifiedEpoch {
+    /// Divide the ratings into blocks and start the worker threads for feature `f`. They are stopped when this is dropped.
+    pub fn new(conf: &RecommendationEngineConf, state: &RecommendationEngineState, residual_cache: &[RatingValue], f: usize) -> Self {
+        let threads = conf.threads;
+        let blocks = Arc::new(Self::blocks(threads, state, residual_cache));
+        let anime_feature = Arc::new(SharedFeature::new(state.anime_features.column(f).iter()));
+        let user_feature = Arc::new(SharedFeature::new(state.user_features.row(f).iter()));
+        let conf = Arc::new(conf.clone());
+        let stratum_barrier = Arc::new(StratumBarrier::new(threads));
+        let (finished_sender, finished) = mpsc::channel();
+
+        let (mut epochs, mut workers) = (Vec::with_capacity(threads), Vec::with_capacity(threads));
+        for anime_group in 0..threads {
+            let (epoch_sender, epoch_receiver) = mpsc::channel::<Option<Vec<u64>>>();
+            let (blocks, anime_feature, user_feature) = (blocks.clone(), anime_feature.clone(), user_feature.clone());
+            let (conf, stratum_barrier, finished_sender) = (conf.clone(), stratum_barrier.clone(), finished_sender.clone());
+            workers.push(thread::spawn(move || {
+                while let Ok(Some(block_seeds)) = epoch_receiver.recv() {
+                    let epoch = panic::catch_unwind(AssertUnwindSafe(|| {
+                        for (stratum, &block_seed) in block_seeds.iter().enumerate() {
+                            let block = &blocks[Self::block_index(threads, anime_group, stratum)];
+                            unsafe { Self::train_block(&conf, block, block_seed, &anime_feature, &user_feature); }
+                            // The blocks of the next stratum share animes / users with the ones of this stratum
+                            if !stratum_barrier.wait() { return false; }
+                        }
+                        return true;
+                    }));
+                    let result = match epoch {
+                        Ok(true) => Ok(()),
+                        Ok(false) => Err(None),
+                        Err(panic) => {
+                            stratum_barrier.poison();
+                            Err(Some(panic))
+                        }
+                    };
+                    let failed = result.is_err();
+                    if finished_sender.send(result).is_err() || failed { return; }
+                }
+            }));
+            epochs.push(epoch_sender);
+        }
+        return Self { threads, anime_feature, user_feature, epochs, finished, workers };
+    }
+
+    /// Block of the anime group `a % threads` and the user group `u % threads` for every rating.
+    fn blocks(threads: usize, state: &RecommendationEngineState, residual_cache: &[RatingValue]) -> Vec<Vec<BlockRating>> {
+        let mut blocks: Vec<Vec<BlockRating>> = (0..threads * threads).map(|_| Vec::new()).collect();
+        for (idx, rating) in state.ratings.ratings.iter().enumerate() {
+            let (a,u) = (rating.animeidx, rating.useridx);
+            blocks[(a % threads) * threads + (u % threads)].push(BlockRating {
+                animeidx: a, useridx: u, rating: rating.rating, residual: residual_cache[idx]
+            });
+        }
+        return blocks;
+    }
+
+    /// Block that the worker of the given anime group trains in the given stratum.
+    fn block_index(threads: usize, anime_group: usize, stratum: usize) -> usize {
+        return anime_group * threads + (anime_group + stratum) % threads;
+    }
+
+    /// Seeds of all blocks of an epoch, by stratum, then by anime group.
+    fn draw_seeds(threads: usize, rng: &mut StdRng) -> Vec<Vec<u64>> {
+        return (0..threads).map(|_| (0..threads).map(|_| rng.gen::<u64>()).collect()).collect();
+    }
+
+    /// SGD over the ratings of one block, in an order shuffled with `seed`.
+    /// No other thread may access the animes and users of the block at the same time.
+    unsafe fn train_block(conf: &RecommendationEngineConf, block: &[BlockRating], seed: u64, anime_feature: &SharedFeature, user_feature: &SharedFeature) {
+        let (learn_rate, regularization_parameter) = (conf.learn_rate, conf.regularization_parameter);
+        let mut order: Vec<Index> = (0..block.len()).collect();
+        shuffle_epoch(conf, &mut order, &mut StdRng::seed_from_u64(seed));
+        for &idx in &order {
+            let rating = &block[idx];
+            let (a,u) = (rating.animeidx, rating.useridx);
+            let (af, uf) = (anime_feature.get(a), user_feature.get(u));
+            let err = rating.rating - (rating.residual + af * uf);
+            anime_feature.set(a, af + learn_rate * (err * uf - regularization_parameter * af));
+            user_feature.set(u, uf + learn_rate * (err * af - regularization_parameter * uf));
+        }
+    }
+
+    /// Train one epoch of feature `f` and write the result back into the feature matrices.
+    /// Every block is shuffled with its own seed drawn from `rng`, so the result does
+    /// not depend on how the threads are scheduled.
+    pub fn run(&self, anime_features: &mut na::DMatrix<RatingValue>, user_features: &mut na::DMatrix<RatingValue>, f: usize, rng: &mut StdRng) {
+        let seeds = Self::draw_seeds(self.threads, rng);
+        for (anime_group, epoch) in self.epochs.iter().enumerate() {
+            let block_seeds = seeds.iter().map(|stratum_seeds| stratum_seeds[anime_group]).collect();
+            // A worker only stops early if one of them panicked, which is resumed below
+            let _ = epoch.send(Some(block_seeds));
+        }
+        let (mut failed, mut worker_panic) = (false, None);
+        for _ in 0..self.threads {
+            match self.finished.recv() {
+                Ok(Ok(())) => (),
+                Ok(Err(panic)) => {
+                    failed = true;
+                    worker_panic = worker_panic.or(panic);
+                },
+                Err(_) => failed = true
+            }
+        }
+        if failed {
+            panic::resume_unwind(worker_panic.unwrap_or_else(|| Box::new("Training thread panicked")));
+        }
+
+        // All workers finished the epoch, so nobody else is accessing the shared features until the next one
+        for a in 0..anime_features.nrows() {
+            anime_features[(a,f)] = unsafe { self.anime_feature.get(a) };
+        }
+        for u in 0..user_features.ncols() {
+            user_features[(f,u)] = unsafe { self.user_feature.get(u) };
+        }
+    }
+}
+impl Drop for StratifiedEpoch {
+    fn drop(&mut self) {
+        for epoch in &self.epochs {
+            let _ = epoch.send(None);
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as na;
+    use rand::{SeedableRng, rngs::StdRng};
+    use std::{collections::HashSet, panic::{self, AssertUnwindSafe}};
+    use crate::dataprovider::{RatingDataProvider, InMemoryDataProvider};
+    use super::{StratifiedEpoch, SharedFeature};
+    use super::super::{RecommendationEngineConf, RecommendationEngineState};
+
+    const THREADS: usize = 4;
+
+    fn conf() -> RecommendationEngineConf {
+        let mut conf = RecommendationEngineConf::default();
+        conf.threads = THREADS;
+        conf.shuffle = true;
+        return conf;
+    }
+
+    fn state() -> RecommendationEngineState {
+        let mut ratings = Vec::new();
+        for userid in 0..30 {
+            for animeid in 0..20 {
+                if (userid * 7 + animeid * 3) % 5 < 3 {
+                    ratings.push((animeid, userid, ((userid + animeid) % 10) as f64 * 0.5));
+                }
+            }
+        }
+        let mut state = RecommendationEngineState::new(InMemoryDataProvider::new(ratings).get().unwrap());
+        let (animes, users) = (state.ratings.animes.len(), state.ratings.users.len());
+        state.anime_features = na::DMatrix::from_fn(animes, 1, |a, _| 0.1 + 0.01 * a as f64);
+        state.user_features = na::DMatrix::from_fn(1, users, |_, u| 0.1 - 0.002 * u as f64);
+        return state;
+    }
+
+    #[test]
+    fn strata_are_disjoint() {
+        let state = state();
+        let blocks = StratifiedEpoch::blocks(THREADS, &state, &vec![0.0; state.ratings.ratings.len()]);
+        assert_eq!(blocks.iter().map(|block| block.len()).sum::<usize>(), state.ratings.ratings.len());
+        for stratum in 0..THREADS {
+            let (mut animes, mut users) = (HashSet::new(), HashSet::new());
+            for anime_group in 0..THREADS {
+                let block = &blocks[StratifiedEpoch::block_index(THREADS, anime_group, stratum)];
+                let block_animes: HashSet<_> = block.iter().map(|r| r.animeidx).collect();
+                let block_users: HashSet<_> = block.iter().map(|r| r.useridx).collect();
+                assert!(animes.is_disjoint(&block_animes) && users.is_disjoint(&block_users));
+                animes.extend(block_animes);
+                users.extend(block_users);
+            }
+        }
+    }
+
+    #[test]
+    fn threads_match_a_single_thread() {
+        let (conf, state) = (conf(), state());
+        let residual_cache = vec![0.0; state.ratings.ratings.len()];
+
+        let (mut anime_features, mut user_features) = (state.anime_features.clone(), state.user_features.clone());
+        let parallel_epoch = StratifiedEpoch::new(&conf, &state, &residual_cache, 0);
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..5 {
+            parallel_epoch.run(&mut anime_features, &mut user_features, 0, &mut rng);
+        }
+
+        // The same blocks and seeds, one block after another on this thread
+        let blocks = StratifiedEpoch::blocks(THREADS, &state, &residual_cache);
+        let anime_feature = SharedFeature::new(state.anime_features.column(0).iter());
+        let user_feature = SharedFeature::new(state.user_features.row(0).iter());
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..5 {
+            let seeds = StratifiedEpoch::draw_seeds(THREADS, &mut rng);
+            for stratum in 0..THREADS {
+                for anime_group in 0..THREADS {
+                    let block = &blocks[StratifiedEpoch::block_index(THREADS, anime_group, stratum)];
+                    unsafe { StratifiedEpoch::train_block(&conf, block, seeds[stratum][anime_group], &anime_feature, &user_feature); }
+                }
+            }
+        }
+
+        for a in 0..anime_features.nrows() {
+            assert_eq!(anime_features[(a,0)], unsafe { anime_feature.get(a) });
+        }
+        for u in 0..user_features.ncols() {
+            assert_eq!(user_features[(0,u)], unsafe { user_feature.get(u) });
+        }
+    }
+
+    #[test]
+    fn worker_panic_is_resumed() {
+        let (conf, mut state) = (conf(), state());
+        // The worker of the last anime's group reads past the end of the shared anime feature
+        let animes = state.ratings.animes.len();
+        state.anime_features = na::DMatrix::from_element(animes - 1, 1, 0.1);
+        let residual_cache = vec![0.0; state.ratings.ratings.len()];
+
+        let (mut anime_features, mut user_features) = (state.anime_features.clone(), state.user_features.clone());
+        let parallel_epoch = StratifiedEpoch::new(&conf, &state, &residual_cache, 0);
+        let mut rng = StdRng::seed_from_u64(42);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            parallel_epoch.run(&mut anime_features, &mut user_features, 0, &mut rng);
+        }));
+        assert!(result.is_err());
+    }
+}
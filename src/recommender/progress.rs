@@ -0,0 +1,181 @@
+use serde_derive::Serialize;
+use std::time::Instant;
+use crate::ratings::{Index, Rating, RatingValue};
+use super::{RecommendationEngineConf, RecommendationEngineState};
+
+/// Errors of one epoch (or ALS iteration) of a training run.
+#[derive(Serialize, Clone)]
+pub struct TrainingCurvePoint {
+    /// Feature that was trained, for trainers that train one feature after another.
+    pub feature: Option<usize>,
+    pub epoch: usize,
+    pub train_mae: RatingValue,
+    pub train_rmse: RatingValue,
+    /// Only recorded if a validation split is configured.
+    pub validation_rmse: Option<RatingValue>,
+    /// Sum of the squared training errors, plus the regularization terms of all trained parameters.
+    pub regularized_loss: RatingValue,
+    /// Seconds since the training started.
+    pub elapsed_secs: f64
+}
+
+#[derive(Serialize, Clone)]
+pub struct TrainingCurve {
+    pub points: Vec<TrainingCurvePoint>
+}
+impl TrainingCurve {
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).unwrap();
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("feature,epoch,train_mae,train_rmse,validation_rmse,regularized_loss,elapsed_secs\n");
+        for point in &self.points {
+            csv += &format!("{},{},{},{},{},{},{}\n",
+                point.feature.map(|f| f.to_string()).unwrap_or_default(), point.epoch,
+                point.train_mae, point.train_rmse,
+                point.validation_rmse.map(|e| e.to_string()).unwrap_or_default(),
+                point.regularized_loss, point.elapsed_secs);
+        }
+        return csv;
+    }
+}
+
+
+pub struct PredictionErrors {
+    pub mae: RatingValue,
+    pub rmse: RatingValue,
+    squared_error_sum: RatingValue
+}
+impl PredictionErrors {
+    pub fn from_sums(absolute_error_sum: RatingValue, squared_error_sum: RatingValue, cnt: usize) -> Self {
+        return Self {
+            mae: absolute_error_sum / cnt as RatingValue,
+            rmse: (squared_error_sum / cnt as RatingValue).sqrt(),
+            squared_error_sum
+        };
+    }
+
+    /// Errors of the given prediction function, which gets every rating together with its index.
+    pub fn measure<P>(ratings: &[Rating], mut predict: P) -> Self
+                where P: FnMut(Index, &Rating) -> RatingValue {
+        let (mut absolute_error_sum, mut squared_error_sum) = (0.0, 0.0);
+        for (idx, rating) in ratings.iter().enumerate() {
+            let err = rating.rating - predict(idx, rating);
+            absolute_error_sum += err.abs();
+            squared_error_sum += err * err;
+        }
+        return Self::from_sums(absolute_error_sum, squared_error_sum, ratings.len());
+    }
+}
+
+/// Squared norms of the feature matrices (and of the biases, for trainers that learn them),
+/// weighted with their regularization parameters.
+pub fn regularization_penalty(conf: &RecommendationEngineConf, state: &RecommendationEngineState, learned_biases: bool) -> RatingValue {
+    let mut penalty = conf.regularization_parameter * (state.anime_features.norm_squared() + state.user_features.norm_squared());
+    if learned_biases {
+        penalty += conf.bias_regularization_parameter * (state.anime_bias.norm_squared() + state.user_bias.norm_squared());
+    }
+    return penalty;
+}
+
+
+/// Keeps track of the epochs of a training run. It decides when an epoch loop stops,
+/// and records the training curve. Once `min_steps` epochs are done, a loop stops when
+/// an epoch improves the error by less than `min_improvement`. This is the validation
+/// RMSE if a validation split is configured, and the training MAE otherwise. The training
+/// error keeps improving while the model starts to fit noise, the validation error doesn't.
+/// Alternatively, every loop runs a fixed amount of epochs (see `with_epochs`).
+pub struct TrainingProgress {
+    min_steps: usize,
+    max_steps: usize,
+    min_improvement: RatingValue,
+    fixed_epochs: Option<Vec<usize>>,
+    started: Instant,
+
+    feature: Option<usize>,
+    epoch: usize,
+    // Amount of epochs of every loop so far
+    loop_epochs: Vec<usize>,
+    last_error: RatingValue,
+    improvement: RatingValue,
+    points: Vec<TrainingCurvePoint>
+}
+impl TrainingProgress {
+    pub fn new(conf: &RecommendationEngineConf) -> Self {
+        return Self {
+            min_steps: conf.min_steps, max_steps: conf.max_steps, min_improvement: conf.min_improvement,
+            fixed_epochs: None,
+            started: Instant::now(),
+            feature: None, epoch: 0, loop_epochs: Vec::new(),
+            last_error: std::f32::MAX as RatingValue, improvement: std::f32::MAX as RatingValue,
+            points: Vec::new()
+        };
+    }
+
+    /// Run the given amount of epochs in every loop (e.g. the ones of `epochs()` of a previous
+    /// training run) instead of stopping on the error. Loops beyond the given ones stop on the error.
+    pub fn with_epochs(conf: &RecommendationEngineConf, epochs: Vec<usize>) -> Self {
+        return Self { fixed_epochs: Some(epochs), ..Self::new(conf) };
+    }
+
+    /// Start the epoch loop of the given feature, or of all features at once (`None`).
+    pub fn start(&mut self, feature: Option<usize>) {
+        self.feature = feature;
+        self.epoch = 0;
+        self.loop_epochs.push(0);
+        self.last_error = std::f32::MAX as RatingValue; // Just use something reasonably big here
+        self.improvement = std::f32::MAX as RatingValue;
+    }
+
+    /// Whether another epoch should be trained.
+    pub fn running(&self) -> bool {
+        let loop_idx = self.loop_epochs.len().saturating_sub(1);
+        if let Some(&epochs) = self.fixed_epochs.as_ref().and_then(|fixed| fixed.get(loop_idx)) {
+            return self.epoch < epochs;
+        }
+        return self.epoch < self.min_steps || (self.epoch < self.max_steps && self.improvement > self.min_improvement);
+    }
+
+    /// Errors on the validation ratings, if there are any.
+    pub fn validate<P>(&self, validation: &[Rating], predict: P) -> Option<PredictionErrors>
+                where P: FnMut(Index, &Rating) -> RatingValue {
+        if validation.is_empty() { return None; }
+        return Some(PredictionErrors::measure(validation, predict));
+    }
+
+    pub fn record(&mut self, train: PredictionErrors, validation: Option<PredictionErrors>, penalty: RatingValue) {
+        let error = match &validation {
+            Some(validation) => validation.rmse,
+            None => train.mae
+        };
+        self.improvement = self.last_error - error;
+        self.last_error = error;
+        debug!(target: "RecommendationEngine", "Epoch {}: Approximation-Error: {} Validation-RMSE: {:?}",
+                self.epoch, train.mae, validation.as_ref().map(|v| v.rmse));
+
+        let elapsed = self.started.elapsed();
+        self.points.push(TrainingCurvePoint {
+            feature: self.feature,
+            epoch: self.epoch,
+            train_mae: train.mae,
+            train_rmse: train.rmse,
+            validation_rmse: validation.map(|v| v.rmse),
+            regularized_loss: train.squared_error_sum + penalty,
+            elapsed_secs: elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
+        });
+        self.epoch += 1;
+        if let Some(epochs) = self.loop_epochs.last_mut() {
+            *epochs = self.epoch;
+        }
+    }
+
+    /// Amount of epochs that every loop ran so far, in the order of the loops.
+    pub fn epochs(&self) -> Vec<usize> {
+        return self.loop_epochs.clone();
+    }
+
+    pub fn finish(self) -> TrainingCurve {
+        return TrainingCurve { points: self.points };
+    }
+}
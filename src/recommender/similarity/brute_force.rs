@@ -0,0 +1,20 @@
+use crate::ratings::{Index, RatingValue};
+use super::NeighborIndex;
+
+/// Exact nearest neighbor search, by comparing the query with every point.
+pub struct BruteForceIndex {
+    points: Vec<Vec<RatingValue>>
+}
+impl BruteForceIndex {
+    pub fn build(points: Vec<Vec<RatingValue>>) -> Self {
+        return Self { points };
+    }
+}
+impl NeighborIndex for BruteForceIndex {
+    fn nearest(&self, point: &[RatingValue], k: usize) -> Option<Vec<Index>> {
+        let mut distances: Vec<(RatingValue, Index)> = self.points.iter().enumerate()
+                    .map(|(idx, other)| (super::squared_distance(point, other), idx)).collect();
+        distances.sort_by(|d0, d1| d0.partial_cmp(d1).unwrap_or(std::cmp::Ordering::Equal));
+        return Some(distances.into_iter().take(k).map(|(_, idx)| idx).collect());
+    }
+}
@@ -0,0 +1,160 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{cmp::{Ordering, Reverse}, collections::BinaryHeap};
+use crate::ratings::{Index, RatingValue};
+use super::{NeighborIndex, squared_distance};
+
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: RatingValue,
+    idx: Index
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self.distance.partial_cmp(&other.distance).unwrap_or(Ordering::Equal)
+                    .then(self.idx.cmp(&other.idx));
+    }
+}
+
+/// Points that were already visited during a search.
+struct VisitedSet(Vec<u64>);
+impl VisitedSet {
+    fn new(points: usize) -> Self {
+        return VisitedSet(vec![0; (points + 63) / 64]);
+    }
+    /// Mark the point as visited. Returns whether it wasn't visited before.
+    fn insert(&mut self, idx: Index) -> bool {
+        let (word, bit) = (idx / 64, 1 << (idx % 64));
+        let unvisited = self.0[word] & bit == 0;
+        self.0[word] |= bit;
+        return unvisited;
+    }
+}
+
+/// Approximate nearest neighbor search with a Hierarchical Navigable Small World graph
+/// (Malkov & Yashunin, 2016). Every point is linked to its nearest neighbors, on a random
+/// amount of layers that get exponentially sparser towards the top. A search greedily walks
+/// the graph from the top layer down, so only a small part of all points is ever compared.
+pub struct HnswIndex {
+    points: Vec<Vec<RatingValue>>,
+    // Neighbors of every point, for each layer the point is part of
+    links: Vec<Vec<Vec<Index>>>,
+    entry_point: Option<Index>,
+    m: usize,
+    ef_construction: usize,
+    ef_search: usize
+}
+impl HnswIndex {
+    /// `m` is the amount of neighbors every point is linked to (twice as many on the bottom layer).
+    /// `ef_construction` / `ef_search` are the amount of candidates kept while building / searching.
+    /// Higher values improve the recall, at the cost of speed.
+    pub fn build(points: Vec<Vec<RatingValue>>, m: usize, ef_construction: usize, ef_search: usize, seed: u64) -> Self {
+        let m = m.max(2);
+        let level_multiplier = 1.0 / (m as f64).ln();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut index = Self {
+            points, links: Vec::new(), entry_point: None,
+            m, ef_construction: ef_construction.max(1), ef_search: ef_search.max(1)
+        };
+        for idx in 0..index.points.len() {
+            let level = (-(1.0 - rng.gen::<f64>()).ln() * level_multiplier) as usize;
+            index.insert(idx, level);
+        }
+        return index;
+    }
+
+    fn max_neighbors(&self, layer: usize) -> usize {
+        return if layer == 0 { 2 * self.m } else { self.m };
+    }
+
+    fn candidate(&self, point: &[RatingValue], idx: Index) -> Candidate {
+        return Candidate { distance: squared_distance(point, &self.points[idx]), idx };
+    }
+
+    fn insert(&mut self, idx: Index, level: usize) {
+        self.links.push(vec![Vec::new(); level + 1]);
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => {
+                self.entry_point = Some(idx);
+                return;
+            }
+        };
+        let top_layer = self.links[entry_point].len() - 1;
+        let point = self.points[idx].clone();
+
+        let mut entry = vec![self.candidate(&point, entry_point)];
+        for layer in (level + 1 ..= top_layer).rev() {
+            entry = self.search_layer(&point, entry, 1, layer);
+        }
+        for layer in (0 ..= level.min(top_layer)).rev() {
+            let found = self.search_layer(&point, entry, self.ef_construction, layer);
+            let neighbors: Vec<Index> = found.iter().take(self.m).map(|c| c.idx).collect();
+            for &neighbor in &neighbors {
+                self.links[neighbor][layer].push(idx);
+                if self.links[neighbor][layer].len() > self.max_neighbors(layer) {
+                    self.shrink_links(neighbor, layer);
+                }
+            }
+            self.links[idx][layer] = neighbors;
+            entry = found;
+        }
+        if level > top_layer {
+            self.entry_point = Some(idx);
+        }
+    }
+
+    /// Only keep the nearest neighbors of a point that got too many links.
+    fn shrink_links(&mut self, idx: Index, layer: usize) {
+        let mut neighbors: Vec<Candidate> = self.links[idx][layer].iter()
+                    .map(|&neighbor| self.candidate(&self.points[idx], neighbor)).collect();
+        neighbors.sort();
+        neighbors.truncate(self.max_neighbors(layer));
+        self.links[idx][layer] = neighbors.into_iter().map(|c| c.idx).collect();
+    }
+
+    /// Best-first search within one layer, starting at `entry`.
+    /// Returns the (at most) `ef` nearest points that were found, nearest first.
+    fn search_layer(&self, point: &[RatingValue], entry: Vec<Candidate>, ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited = VisitedSet::new(self.points.len());
+        for c in &entry { visited.insert(c.idx); }
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = entry.iter().map(|&c| Reverse(c)).collect();
+        let mut found: BinaryHeap<Candidate> = entry.into_iter().collect();
+        while found.len() > ef { found.pop(); }
+
+        while let Some(Reverse(nearest)) = candidates.pop() {
+            // Nothing that is reachable from here can still improve the result
+            if found.peek().map_or(false, |furthest| nearest.distance > furthest.distance) { break; }
+
+            for &neighbor in &self.links[nearest.idx][layer] {
+                if !visited.insert(neighbor) { continue; }
+                let candidate = self.candidate(point, neighbor);
+                if found.len() < ef || found.peek().map_or(true, |furthest| candidate.distance < furthest.distance) {
+                    candidates.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef { found.pop(); }
+                }
+            }
+        }
+        return found.into_sorted_vec();
+    }
+}
+impl NeighborIndex for HnswIndex {
+    fn nearest(&self, point: &[RatingValue], k: usize) -> Option<Vec<Index>> {
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => return Some(Vec::new())
+        };
+        let mut entry = vec![self.candidate(point, entry_point)];
+        for layer in (1..self.links[entry_point].len()).rev() {
+            entry = self.search_layer(point, entry, 1, layer);
+        }
+        let found = self.search_layer(point, entry, self.ef_search.max(k), 0);
+        return Some(found.into_iter().take(k).map(|c| c.idx).collect());
+    }
+}
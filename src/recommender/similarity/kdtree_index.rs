@@ -0,0 +1,25 @@
+use kdtree::KdTree;
+use crate::ratings::{Index, RatingValue};
+use super::NeighborIndex;
+
+/// Exact nearest neighbor search with a kd-tree. This is fast for a few dimensions,
+/// but degenerates towards a linear scan with many features.
+pub struct KdTreeIndex {
+    tree: KdTree<RatingValue, Index, Vec<RatingValue>>
+}
+impl KdTreeIndex {
+    pub fn build(points: Vec<Vec<RatingValue>>) -> Self {
+        let dimensions = points.first().map(|p| p.len()).unwrap_or(0);
+        let mut tree = KdTree::new_with_capacity(dimensions, points.len());
+        for (idx, point) in points.into_iter().enumerate() {
+            tree.add(point, idx).expect("Building spatial tree for features failed");
+        }
+        return Self { tree };
+    }
+}
+impl NeighborIndex for KdTreeIndex {
+    fn nearest(&self, point: &[RatingValue], k: usize) -> Option<Vec<Index>> {
+        let nearest = self.tree.nearest(point, k, &super::squared_distance).ok()?;
+        return Some(nearest.into_iter().map(|(_, &idx)| idx).collect());
+    }
+}
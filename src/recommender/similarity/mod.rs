@@ -0,0 +1,176 @@
+use nalgebra as na;
+use serde_derive::Serialize;
+use std::time::{Duration, Instant};
+use crate::ratings::{Index, RatingValue};
+
+mod kdtree_index;
+mod brute_force;
+mod hnsw;
+use self::kdtree_index::KdTreeIndex;
+use self::brute_force::BruteForceIndex;
+use self::hnsw::HnswIndex;
+
+/// Metric that similar users / animes are found by. All metrics report their
+/// similarity as a score in [0, 1], where higher scores mean more similar.
+#[derive(Serialize, Clone, Copy)]
+pub enum SimilarityMetric {
+    /// Euclidean distance `d` between the feature vectors, scored as `1 / (1 + d)`.
+    Euclidean,
+    /// Cosine of the angle `c` between the feature vectors, scored as `(1 + c) / 2`.
+    Cosine,
+    /// Inner product `p` of the feature vectors, scored as `1 / (1 + e^-p)`.
+    DotProduct
+}
+impl SimilarityMetric {
+    pub fn similarity(&self, v0: &na::DVector<RatingValue>, v1: &na::DVector<RatingValue>) -> RatingValue {
+        return match self {
+            SimilarityMetric::Euclidean => 1.0 / (1.0 + (v0 - v1).norm()),
+            SimilarityMetric::Cosine => {
+                let norms = v0.norm() * v1.norm();
+                let cosine = if norms > 0.0 { v0.dot(v1) / norms } else { 0.0 };
+                (1.0 + cosine.max(-1.0).min(1.0)) / 2.0
+            },
+            SimilarityMetric::DotProduct => 1.0 / (1.0 + (-v0.dot(v1)).exp())
+        };
+    }
+}
+
+
+/// Data structure that similar users / animes are searched with.
+#[derive(Serialize, Clone, Copy)]
+pub enum NeighborIndexKind {
+    /// Exact search with a kd-tree. Slow with many features.
+    KdTree,
+    /// Exact search, comparing every user / anime. Slow for many users / animes.
+    BruteForce,
+    /// Approximate search with a HNSW graph (see `HnswIndex`).
+    Hnsw { m: usize, ef_construction: usize, ef_search: usize }
+}
+
+/// Nearest neighbor search over points, by euclidean distance.
+trait NeighborIndex {
+    /// Indices of the `k` points nearest to `point` (approximately, depending on the index), nearest first.
+    fn nearest(&self, point: &[RatingValue], k: usize) -> Option<Vec<Index>>;
+}
+
+fn squared_distance(p0: &[RatingValue], p1: &[RatingValue]) -> RatingValue {
+    return p0.iter().zip(p1).map(|(p0v, p1v)| (p0v - p1v) * (p0v - p1v)).sum::<RatingValue>();
+}
+
+
+/// Spatial index over feature vectors, which finds the vectors most similar to a query.
+/// The underlying indices can only search by euclidean distance, so the vectors are transformed
+/// such that the nearest neighbors are the most similar ones for the configured metric:
+/// - Cosine: All vectors are normalized, where the euclidean distance only depends on the angle.
+/// - DotProduct: Every vector `x` gets an additional dimension `sqrt(M² - |x|²)`, where `M` is the
+///   largest norm of all vectors (the query gets a 0). The squared distance to a query `q` then
+///   is `|q|² + M² - 2 q·x`, which is smallest for the largest inner product.
+pub struct SimilarityIndex {
+    metric: SimilarityMetric,
+    max_norm: RatingValue,
+    index: Box<dyn NeighborIndex + Send + Sync>
+}
+impl SimilarityIndex {
+    pub fn empty(metric: SimilarityMetric) -> Self {
+        return Self { metric, max_norm: 0.0, index: Box::new(BruteForceIndex::build(Vec::new())) };
+    }
+
+    /// Build the index over the given vectors. Their position becomes their index.
+    pub fn build(metric: SimilarityMetric, kind: NeighborIndexKind, seed: u64, vectors: &[na::DVector<RatingValue>]) -> Self {
+        let max_norm = vectors.iter().map(|v| v.norm()).fold(0.0, RatingValue::max);
+        let points = vectors.iter().map(|v| Self::point(metric, max_norm, v, true)).collect();
+        let index: Box<dyn NeighborIndex + Send + Sync> = match kind {
+            NeighborIndexKind::KdTree => Box::new(KdTreeIndex::build(points)),
+            NeighborIndexKind::BruteForce => Box::new(BruteForceIndex::build(points)),
+            NeighborIndexKind::Hnsw { m, ef_construction, ef_search } => Box::new(HnswIndex::build(points, m, ef_construction, ef_search, seed))
+        };
+        return Self { metric, max_norm, index };
+    }
+
+    /// The `k` indexed vectors most similar to `query`, most similar first.
+    pub fn nearest(&self, query: &na::DVector<RatingValue>, k: usize) -> Option<Vec<Index>> {
+        return self.index.nearest(&Self::point(self.metric, self.max_norm, query, false), k);
+    }
+
+    fn point(metric: SimilarityMetric, max_norm: RatingValue, vector: &na::DVector<RatingValue>, indexed: bool) -> Vec<RatingValue> {
+        let mut point: Vec<RatingValue> = match metric {
+            SimilarityMetric::Cosine if vector.norm() > 0.0 => vector.normalize().iter().cloned().collect(),
+            _ => vector.iter().cloned().collect()
+        };
+        // Every metric gets the additional dimension, so all points have the same dimensions
+        point.push(match metric {
+            SimilarityMetric::DotProduct if indexed => (max_norm.powi(2) - vector.norm_squared()).max(0.0).sqrt(),
+            _ => 0.0
+        });
+        return point;
+    }
+}
+
+
+/// Speed and recall of one kind of index, on the feature vectors of a trained model.
+#[derive(Serialize)]
+pub struct IndexBenchmark {
+    /// `animes` or `users`
+    pub space: &'static str,
+    pub index: NeighborIndexKind,
+    pub points: usize,
+    pub build_millis: f64,
+    pub queries: usize,
+    /// Average time per query.
+    pub query_micros: f64,
+    /// Fraction of the exact `k` nearest neighbors that the index found.
+    pub recall_at_k: f64
+}
+
+/// Build every given kind of index over the vectors, and query it with `queries` of the vectors.
+pub fn benchmark(space: &'static str, metric: SimilarityMetric, seed: u64, vectors: &[na::DVector<RatingValue>],
+                 kinds: &[NeighborIndexKind], k: usize, queries: usize) -> Vec<IndexBenchmark> {
+    let queries: Vec<&na::DVector<RatingValue>> = match vectors.len() {
+        0 => Vec::new(),
+        len => (0..queries.min(len)).map(|q| &vectors[q * len / queries.min(len)]).collect()
+    };
+    let exact = SimilarityIndex::build(metric, NeighborIndexKind::BruteForce, seed, vectors);
+    let exact_results: Vec<Vec<Index>> = queries.iter().map(|q| exact.nearest(q, k).unwrap_or_default()).collect();
+
+    return kinds.iter().map(|&kind| {
+        let started = Instant::now();
+        let index = SimilarityIndex::build(metric, kind, seed, vectors);
+        let build_time = started.elapsed();
+
+        let started = Instant::now();
+        let results: Vec<Vec<Index>> = queries.iter().map(|q| index.nearest(q, k).unwrap_or_default()).collect();
+        let query_time = started.elapsed();
+
+        let (mut found, mut expected) = (0, 0);
+        for (result, exact_result) in results.iter().zip(&exact_results) {
+            found += result.iter().filter(|idx| exact_result.contains(idx)).count();
+            expected += exact_result.len();
+        }
+        IndexBenchmark {
+            space, index: kind, points: vectors.len(),
+            build_millis: secs(build_time) * 1e3,
+            queries: queries.len(),
+            query_micros: secs(query_time) * 1e6 / queries.len().max(1) as f64,
+            recall_at_k: found as f64 / expected.max(1) as f64
+        }
+    }).collect();
+}
+
+fn secs(duration: Duration) -> f64 {
+    return duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9;
+}
+
+pub fn format_table(results: &[IndexBenchmark]) -> String {
+    let mut table = format!("{:>6} | {:>10} | {:>8} | {:>12} | {:>12} | {:>8}\n",
+                            "space", "index", "points", "build [ms]", "query [µs]", "recall");
+    for result in results {
+        let index = match result.index {
+            NeighborIndexKind::KdTree => "KdTree",
+            NeighborIndexKind::BruteForce => "BruteForce",
+            NeighborIndexKind::Hnsw { .. } => "Hnsw"
+        };
+        table += &format!("{:>6} | {:>10} | {:>8} | {:>12.2} | {:>12.2} | {:>8.4}\n",
+                          result.space, index, result.points, result.build_millis, result.query_micros, result.recall_at_k);
+    }
+    return table;
+}
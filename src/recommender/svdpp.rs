@@ -0,0 +1,115 @@
+use nalgebra as na;
+use rand::{SeedableRng, rngs::StdRng};
+use crate::ratings::{Index, RatingValue};
+use super::{Trainer, RecommendationEngineConf, RecommendationEngineState, initial_features, shuffle_epoch};
+use super::progress::{TrainingProgress, PredictionErrors, regularization_penalty};
+
+/// SVD++ (Koren, 2008). Extends biased matrix factorization by the implicit information
+/// of which animes a user rated at all. Every anime gets a second feature vector, and
+/// a user's taste is their own feature vector plus the normalized sum of the implicit
+/// feature vectors of all animes they rated. This helps users with only a few ratings.
+pub struct SvdPlusPlusTrainer;
+
+impl Trainer for SvdPlusPlusTrainer {
+    fn train(&self, conf: &RecommendationEngineConf, state: &mut RecommendationEngineState, progress: &mut TrainingProgress) {
+        let (anime_cnt, user_cnt) = (state.ratings.animes.len(), state.ratings.users.len());
+        let (learn_rate, reg, bias_reg) = (conf.learn_rate, conf.regularization_parameter, conf.bias_regularization_parameter);
+
+        let mut ratings_by_user: Vec<Vec<(Index, RatingValue)>> = vec![Vec::new(); user_cnt];
+        for rating in &state.ratings.ratings {
+            ratings_by_user[rating.useridx].push((rating.animeidx, rating.rating));
+        }
+
+        // Start from the damped averages, split into a global mean and the deviations from it
+        state.global_bias = state.global_rating_avg;
+        state.anime_bias = state.anime_rating_avg.add_scalar(-state.global_rating_avg);
+        state.user_bias = state.user_avg_offset.clone();
+
+        let mut rng = StdRng::seed_from_u64(conf.seed);
+        state.anime_features = initial_features(conf, anime_cnt, conf.features, &mut rng);
+        state.user_features = initial_features(conf, conf.features, user_cnt, &mut rng);
+        let mut implicit_features = na::DMatrix::from_element(anime_cnt, conf.features, 0.0);
+
+        // Ratings are visited user by user, because the implicit features of a user are shared by all their ratings
+        let mut user_order: Vec<Index> = (0..user_cnt).collect();
+        progress.start(None);
+        while progress.running() {
+            let (mut error_sum, mut squared_error_sum) = (0.0, 0.0);
+            shuffle_epoch(conf, &mut user_order, &mut rng);
+            for &u in &user_order {
+                shuffle_epoch(conf, &mut ratings_by_user[u], &mut rng);
+                let user_ratings = &ratings_by_user[u];
+                if user_ratings.is_empty() { continue; }
+                let norm = 1.0 / (user_ratings.len() as RatingValue).sqrt();
+                let implicit_sum = Self::implicit_sum(&implicit_features, user_ratings, norm);
+
+                // The implicit features are shared by all ratings of this user. Instead of updating them
+                // after every rating, their gradient is accumulated and applied once, averaged.
+                let mut implicit_gradient = na::DVector::from_element(conf.features, 0.0);
+                for &(a, rating) in user_ratings {
+                    let user_vector = state.user_features.column(u) + &implicit_sum;
+                    let prediction = state.global_bias + state.anime_bias[a] + state.user_bias[u]
+                                        + state.anime_features.row(a).transpose().dot(&user_vector);
+                    let err = rating - prediction;
+                    error_sum += err.abs();
+                    squared_error_sum += err * err;
+
+                    state.global_bias += learn_rate * err;
+                    state.anime_bias[a] += learn_rate * (err - bias_reg * state.anime_bias[a]);
+                    state.user_bias[u] += learn_rate * (err - bias_reg * state.user_bias[u]);
+                    for f in 0..conf.features {
+                        let af = state.anime_features[(a,f)];
+                        let uf = state.user_features[(f,u)];
+                        state.anime_features[(a,f)] += learn_rate * (err * user_vector[f] - reg * af);
+                        state.user_features[(f,u)] += learn_rate * (err * af - reg * uf);
+                        implicit_gradient[f] += err * norm * af;
+                    }
+                }
+                for &(j, _) in user_ratings {
+                    for f in 0..conf.features {
+                        let yf = implicit_features[(j,f)];
+                        implicit_features[(j,f)] += learn_rate * (implicit_gradient[f] / user_ratings.len() as RatingValue - reg * yf);
+                    }
+                }
+            }
+
+            // Error during the epoch, to avoid another pass over all ratings
+            let train = PredictionErrors::from_sums(error_sum, squared_error_sum, state.ratings.ratings.len());
+            let validation = if state.validation.is_empty() { None } else {
+                let implicit_user_features = Self::implicit_user_features(&implicit_features, &ratings_by_user);
+                progress.validate(&state.validation, |_, r| {
+                    let (a,u) = (r.animeidx, r.useridx);
+                    let user_vector = state.user_features.column(u) + implicit_user_features.column(u);
+                    state.baseline(a, u) + state.anime_features.row(a).transpose().dot(&user_vector)
+                })
+            };
+            state.approximation_error = train.mae;
+            let penalty = regularization_penalty(conf, state, true) + reg * implicit_features.norm_squared();
+            progress.record(train, validation, penalty);
+        }
+
+        // Precompute every user's implicit sum, so predictions don't need the ratings
+        state.implicit_user_features = Some(Self::implicit_user_features(&implicit_features, &ratings_by_user));
+        state.implicit_anime_features = Some(implicit_features);
+    }
+}
+
+impl SvdPlusPlusTrainer {
+    fn implicit_user_features(implicit_features: &na::DMatrix<RatingValue>, ratings_by_user: &[Vec<(Index, RatingValue)>]) -> na::DMatrix<RatingValue> {
+        let mut implicit_user_features = na::DMatrix::from_element(implicit_features.ncols(), ratings_by_user.len(), 0.0);
+        for (u, user_ratings) in ratings_by_user.iter().enumerate() {
+            if user_ratings.is_empty() { continue; }
+            let norm = 1.0 / (user_ratings.len() as RatingValue).sqrt();
+            implicit_user_features.set_column(u, &Self::implicit_sum(implicit_features, user_ratings, norm));
+        }
+        return implicit_user_features;
+    }
+
+    fn implicit_sum(implicit_features: &na::DMatrix<RatingValue>, user_ratings: &[(Index, RatingValue)], norm: RatingValue) -> na::DVector<RatingValue> {
+        let mut sum = na::DVector::from_element(implicit_features.ncols(), 0.0);
+        for &(j, _) in user_ratings {
+            sum += implicit_features.row(j).transpose();
+        }
+        return sum * norm;
+    }
+}
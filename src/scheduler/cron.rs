@@ -0,0 +1,171 @@
+use chrono::{NaiveDate, NaiveDateTime, Datelike, Timelike, Duration};
+use std::str::FromStr;
+
+/// Cron runs after this many years without a match are considered to never happen (e.g. `0 0 31 2 *`).
+/// Leap days can be up to 8 years apart.
+const MAX_SEARCH_YEARS: i32 = 9;
+
+/// Classic 5-field cron expression: `minute hour day-of-month month day-of-week`, evaluated in UTC.
+/// Fields support `*`, values, ranges (`a-b`), lists (`a,b`) and steps (`*/n`, `a-b/n`).
+/// Day-of-week goes from 0 (Sunday) to 6, 7 is Sunday as well. If both day-of-month and day-of-week
+/// are restricted, a day matches if either of them matches.
+#[derive(Clone, Debug)]
+pub struct CronExpression {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    days_of_month_restricted: bool,
+    days_of_week_restricted: bool
+}
+
+impl CronExpression {
+    /// First minute after the given unix timestamp that matches this expression, as unix timestamp.
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let after = NaiveDateTime::from_timestamp(after as i64, 0);
+        let mut t = after.date().and_hms(after.hour(), after.minute(), 0) + Duration::minutes(1);
+        let give_up_year = t.year() + MAX_SEARCH_YEARS;
+        while t.year() <= give_up_year {
+            if !Self::contains(self.months, t.month()) {
+                let (year, month) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = NaiveDate::from_ymd(year, month, 1).and_hms(0, 0, 0);
+            } else if !self.matches_day(&t.date()) {
+                t = t.date().succ().and_hms(0, 0, 0);
+            } else if !Self::contains(self.hours, t.hour()) {
+                t = t.date().and_hms(t.hour(), 0, 0) + Duration::hours(1);
+            } else if !Self::contains(self.minutes, t.minute()) {
+                t = t + Duration::minutes(1);
+            } else {
+                return Some(t.timestamp() as u64);
+            }
+        }
+        return None;
+    }
+
+    fn matches_day(&self, date: &NaiveDate) -> bool {
+        let dom = Self::contains(self.days_of_month, date.day());
+        let dow = Self::contains(self.days_of_week, date.weekday().num_days_from_sunday());
+        return match (self.days_of_month_restricted, self.days_of_week_restricted) {
+            (true, true) => dom || dow,
+            _ => dom && dow
+        };
+    }
+
+    fn contains(field: u64, value: u32) -> bool {
+        return field & (1 << value) != 0;
+    }
+
+    /// Parse one field into a bitmask of its values. Returns the mask and whether the field was restricted.
+    /// Like in Vixie cron, fields starting with `*` (e.g. `*/2`) are not restricted.
+    fn parse_field(field: &str, name: &str, min: u32, max: u32) -> Result<(u64, bool), String> {
+        let parse_value = |value: &str| -> Result<u32, String> {
+            return match value.parse::<u32>() {
+                Ok(value) if value >= min && value <= max => Ok(value),
+                _ => Err(format!("{} has to be a number from {} to {}, not {}", name, min, max, value))
+            };
+        };
+        let mut mask = 0;
+        for item in field.split(',') {
+            let (range, step) = match item.find('/') {
+                Some(idx) => match item[idx + 1..].parse::<u32>() {
+                    Ok(step) if step > 0 => (&item[..idx], step),
+                    _ => return Err(format!("Invalid step in {}: {}", name, item))
+                },
+                None => (item, 1)
+            };
+            let (first, last) = match (range, range.find('-')) {
+                ("*", _) => (min, max),
+                (_, Some(idx)) => (parse_value(&range[..idx])?, parse_value(&range[idx + 1..])?),
+                // A single value with a step (e.g. 5/15) runs until the end of the range
+                (_, None) if step > 1 => (parse_value(range)?, max),
+                (_, None) => { let value = parse_value(range)?; (value, value) }
+            };
+            if first > last {
+                return Err(format!("Invalid range in {}: {}", name, item));
+            }
+            for value in (first..=last).step_by(step as usize) {
+                mask |= 1 << value;
+            }
+        }
+        return Ok((mask, !field.starts_with('*')));
+    }
+}
+
+impl FromStr for CronExpression {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Expected 5 fields (minute hour day-of-month month day-of-week), got {}", fields.len()));
+        }
+        let (minutes, _) = Self::parse_field(fields[0], "minute", 0, 59)?;
+        let (hours, _) = Self::parse_field(fields[1], "hour", 0, 23)?;
+        let (days_of_month, days_of_month_restricted) = Self::parse_field(fields[2], "day-of-month", 1, 31)?;
+        let (months, _) = Self::parse_field(fields[3], "month", 1, 12)?;
+        let (mut days_of_week, days_of_week_restricted) = Self::parse_field(fields[4], "day-of-week", 0, 7)?;
+        // 7 is an alias for Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+        return Ok(Self { minutes, hours, days_of_month, months, days_of_week, days_of_month_restricted, days_of_week_restricted });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::CronExpression;
+    use chrono::NaiveDate;
+
+    fn timestamp(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> u64 {
+        return NaiveDate::from_ymd(year, month, day).and_hms(hour, minute, 0).timestamp() as u64;
+    }
+
+    fn next_after(expression: &str, after: u64) -> Option<u64> {
+        return expression.parse::<CronExpression>().unwrap().next_after(after);
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // 2024-01-01 is a Monday: the next Friday comes before the 13th
+        assert_eq!(next_after("0 0 13 * 5", timestamp(2024, 1, 1, 0, 0)), Some(timestamp(2024, 1, 5, 0, 0)));
+        // ... and after Friday the 12th, the 13th comes first
+        assert_eq!(next_after("0 0 13 * 5", timestamp(2024, 1, 12, 0, 0)), Some(timestamp(2024, 1, 13, 0, 0)));
+    }
+
+    #[test]
+    fn stepped_wildcard_is_not_restricted() {
+        // Both have to match: the first Sunday, Tuesday, Thursday or Saturday that is the 1st of a month
+        assert_eq!(next_after("0 0 1 * */2", timestamp(2024, 1, 1, 0, 0)), Some(timestamp(2024, 2, 1, 0, 0)));
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        assert_eq!(next_after("0 12 * * 7", timestamp(2024, 1, 1, 0, 0)), Some(timestamp(2024, 1, 7, 12, 0)));
+        assert_eq!(next_after("0 12 * * 0", timestamp(2024, 1, 1, 0, 0)), Some(timestamp(2024, 1, 7, 12, 0)));
+    }
+
+    #[test]
+    fn impossible_date_never_runs() {
+        assert_eq!(next_after("0 0 31 2 *", timestamp(2024, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn rolls_over_months_and_years() {
+        // February has no 31st
+        assert_eq!(next_after("30 23 31 * *", timestamp(2024, 1, 31, 23, 30)), Some(timestamp(2024, 3, 31, 23, 30)));
+        assert_eq!(next_after("0 0 1 1 *", timestamp(2024, 6, 1, 0, 0)), Some(timestamp(2025, 1, 1, 0, 0)));
+        // Leap days are only due every 4 years
+        assert_eq!(next_after("0 0 29 2 *", timestamp(2024, 3, 1, 0, 0)), Some(timestamp(2028, 2, 29, 0, 0)));
+    }
+}
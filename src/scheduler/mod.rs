@@ -0,0 +1,142 @@
+mod cron;
+
+use serde_derive::Serialize;
+use std::{thread, sync::{Arc, Mutex, Condvar}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+pub use self::cron::CronExpression;
+
+/// When a job is due.
+#[derive(Clone)]
+pub enum Schedule {
+    /// Every `every_secs` seconds.
+    Interval { every_secs: u64 },
+    Cron(CronExpression)
+}
+impl Schedule {
+    /// First time after `after` (unix timestamp) at which a run is due. None if there is none.
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        return match self {
+            Schedule::Interval { every_secs } => after.checked_add(*every_secs),
+            Schedule::Cron(expression) => expression.next_after(after)
+        };
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct SchedulerStatus {
+    pub paused: bool,
+    /// Whether a run is in progress right now.
+    pub running: bool,
+    /// Unix timestamps of the start and end of the last run.
+    pub last_run_started: Option<u64>,
+    pub last_run_finished: Option<u64>,
+    pub last_run_succeeded: Option<bool>,
+    /// Unix timestamp of the next scheduled run. None while paused.
+    pub next_run: Option<u64>,
+    /// Amount of scheduled runs that were skipped, because the previous run was still in progress.
+    pub skipped_runs: usize
+}
+
+/// A job returns whether it succeeded.
+pub type Job = dyn Fn() -> bool + Send + Sync;
+
+/// Runs a job on its own thread whenever it is due according to the schedule.
+/// There is never more than one run of the job at a time - a run that is due while the
+/// previous one is still in progress is skipped.
+pub struct Scheduler {
+    schedule: Schedule,
+    job: Box<Job>,
+    status: Mutex<SchedulerStatus>,
+    wakeup: Condvar
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule, job: Box<Job>) -> Self {
+        let status = SchedulerStatus {
+            paused: false, running: false,
+            last_run_started: None, last_run_finished: None, last_run_succeeded: None,
+            next_run: None, skipped_runs: 0
+        };
+        return Self { schedule, job, status: Mutex::new(status), wakeup: Condvar::new() };
+    }
+
+    pub fn now() -> u64 {
+        return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    }
+
+    /// Start the thread that waits for the runs to become due. The first run is the first one that is due
+    /// after `last_run` (unix timestamp) - if that is in the past, it is started right away.
+    pub fn start(self: &Arc<Self>, last_run: u64) {
+        self.status.lock().unwrap().next_run = self.schedule.next_after(last_run);
+        let scheduler = self.clone();
+        thread::spawn(move || scheduler.wait_for_runs());
+    }
+
+    fn wait_for_runs(self: Arc<Self>) {
+        let mut status = self.status.lock().unwrap();
+        loop {
+            let now = Self::now();
+            status = match status.next_run {
+                // Paused, or there are no further runs. Wait until resumed.
+                None => self.wakeup.wait(status).unwrap(),
+                Some(next_run) if next_run > now => self.wakeup.wait_timeout(status, Duration::from_secs(next_run - now)).unwrap().0,
+                Some(_) => {
+                    status.next_run = self.schedule.next_after(now);
+                    if status.running {
+                        warn!(target: "Scheduler", "Skipping run, because the previous one is still in progress");
+                        status.skipped_runs += 1;
+                    } else {
+                        self.spawn_run(&mut status);
+                    }
+                    status
+                }
+            };
+        }
+    }
+
+    fn spawn_run(self: &Arc<Self>, status: &mut SchedulerStatus) {
+        status.running = true;
+        status.last_run_started = Some(Self::now());
+        let scheduler = self.clone();
+        thread::spawn(move || {
+            let succeeded = (scheduler.job)();
+            let mut status = scheduler.status.lock().unwrap();
+            status.running = false;
+            status.last_run_finished = Some(Self::now());
+            status.last_run_succeeded = Some(succeeded);
+        });
+    }
+
+    /// Start a run right away, independent of the schedule. Returns false if a run is already in progress.
+    pub fn run_now(self: &Arc<Self>) -> bool {
+        let mut status = self.status.lock().unwrap();
+        if status.running {
+            return false;
+        }
+        self.spawn_run(&mut status);
+        return true;
+    }
+
+    /// Stop starting scheduled runs. A run that is in progress is finished.
+    pub fn pause(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.paused = true;
+        status.next_run = None;
+        self.wakeup.notify_all();
+    }
+
+    /// Continue with the next run that is due from now on.
+    pub fn resume(&self) {
+        let mut status = self.status.lock().unwrap();
+        if !status.paused {
+            return;
+        }
+        status.paused = false;
+        status.next_run = self.schedule.next_after(Self::now());
+        self.wakeup.notify_all();
+    }
+
+    pub fn status(&self) -> SchedulerStatus {
+        return self.status.lock().unwrap().clone();
+    }
+}
@@ -0,0 +1,211 @@
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use serde_derive::Serialize;
+use serde_json::json;
+use std::{thread, sync::{Arc, Mutex, mpsc}};
+use crate::ratings::{RatingValue, RatingContainer};
+use crate::recommender::RecommendationEngineConf;
+use crate::settings::RecoChanSettingsTraining;
+use crate::evaluation::{self, HoldoutSplit, EvaluationParams, EvaluationReport, EvaluationError};
+
+/// How the configurations to try are picked from the `SearchSpace`.
+pub enum SearchStrategy {
+    /// Try every combination of the given values.
+    Grid,
+    /// Try `samples` randomly chosen combinations of the given values.
+    Random { samples: usize, seed: u64 }
+}
+
+/// Metric of the `EvaluationReport` that configurations are ranked by.
+#[derive(Clone, Copy)]
+pub enum SearchMetric {
+    Rmse,
+    Mae,
+    NdcgAtK,
+    MapAtK,
+    RecallAtK,
+    Auc
+}
+impl SearchMetric {
+    /// Value of this metric in the report, if it was measured.
+    pub fn value(&self, report: &EvaluationReport) -> Option<f64> {
+        return match self {
+            SearchMetric::Rmse => report.rmse,
+            SearchMetric::Mae => report.mae,
+            SearchMetric::NdcgAtK => Some(report.ndcg_at_k),
+            SearchMetric::MapAtK => Some(report.map_at_k),
+            SearchMetric::RecallAtK => Some(report.recall_at_k),
+            SearchMetric::Auc => Some(report.auc)
+        };
+    }
+
+    fn higher_is_better(&self) -> bool {
+        return match self {
+            SearchMetric::Rmse | SearchMetric::Mae => false,
+            SearchMetric::NdcgAtK | SearchMetric::MapAtK | SearchMetric::RecallAtK | SearchMetric::Auc => true
+        };
+    }
+}
+
+/// Candidate values for each of the searched hyperparameters.
+pub struct SearchSpace {
+    pub features: Vec<usize>,
+    pub learn_rate: Vec<RatingValue>,
+    pub regularization_parameter: Vec<RatingValue>,
+    pub k: Vec<RatingValue>
+}
+impl SearchSpace {
+    fn grid(&self) -> Vec<SearchCandidate> {
+        let mut result = Vec::new();
+        for &features in &self.features {
+            for &learn_rate in &self.learn_rate {
+                for &regularization_parameter in &self.regularization_parameter {
+                    for &k in &self.k {
+                        result.push(SearchCandidate { features, learn_rate, regularization_parameter, k });
+                    }
+                }
+            }
+        }
+        return result;
+    }
+
+    pub fn candidates(&self, strategy: &SearchStrategy) -> Vec<SearchCandidate> {
+        let mut grid = self.grid();
+        if let SearchStrategy::Random { samples, seed } = *strategy {
+            grid.shuffle(&mut StdRng::seed_from_u64(seed));
+            grid.truncate(samples);
+        }
+        return grid;
+    }
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct SearchCandidate {
+    pub features: usize,
+    pub learn_rate: RatingValue,
+    pub regularization_parameter: RatingValue,
+    pub k: RatingValue
+}
+impl SearchCandidate {
+    fn apply(&self, base: &RecommendationEngineConf) -> RecommendationEngineConf {
+        let mut config = base.clone();
+        config.features = self.features;
+        config.learn_rate = self.learn_rate;
+        config.regularization_parameter = self.regularization_parameter;
+        config.k = self.k;
+        return config;
+    }
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    pub rank: usize,
+    pub candidate: SearchCandidate,
+    pub report: EvaluationReport
+}
+
+
+/// Train and evaluate every candidate configuration on the same holdout split of `data`,
+/// using `threads` worker threads. Candidates that are not searched are taken from `base`.
+/// Returns the results ranked by `metric`, best first, or the first error any evaluation ran into.
+pub fn search(base: &RecommendationEngineConf, data: &RatingContainer, split: &HoldoutSplit, params: &EvaluationParams,
+                candidates: Vec<SearchCandidate>, metric: SearchMetric, threads: usize) -> Result<Vec<SearchResult>, EvaluationError> {
+    let (train, test) = split.split(data);
+    info!(target: "Search", "Evaluating {} configurations on {} training and {} test ratings", candidates.len(), train.len(), test.len());
+
+    let catalog_size = data.animes.len();
+    let split = Arc::new((train, test));
+    let queue = Arc::new(Mutex::new(candidates.into_iter().map(|c| (c, c.apply(base))).collect::<Vec<_>>()));
+    let (result_tx, result_rx) = mpsc::channel();
+
+    let workers: Vec<_> = (0..usize::max(threads, 1)).map(|_| {
+        let (split, queue, result_tx, params) = (split.clone(), queue.clone(), result_tx.clone(), params.clone());
+        thread::spawn(move || {
+            loop {
+                // Release the lock before training, so other workers can fetch their next candidate
+                let next = queue.lock().unwrap().pop();
+                let (candidate, config) = match next {
+                    Some(next) => next,
+                    None => break
+                };
+                let report = match evaluation::evaluate_split(config, catalog_size, split.0.clone(), &split.1, &params) {
+                    Ok(report) => report,
+                    Err(e) => {
+                        // No point in trying the remaining candidates
+                        queue.lock().unwrap().clear();
+                        result_tx.send(Err(e)).unwrap();
+                        break;
+                    }
+                };
+                info!(target: "Search", "features={} learn_rate={} regularization_parameter={} k={}: {}={}",
+                        candidate.features, candidate.learn_rate, candidate.regularization_parameter, candidate.k,
+                        metric_name(metric), format_metric(metric.value(&report)));
+                result_tx.send(Ok((candidate, report))).unwrap();
+            }
+        })
+    }).collect();
+    drop(result_tx);
+
+    let results: Result<Vec<(SearchCandidate, EvaluationReport)>, EvaluationError> = result_rx.iter().collect();
+    for worker in workers {
+        worker.join().expect("Search worker panicked");
+    }
+    let mut results = results?;
+
+    results.sort_by(|(_, r0), (_, r1)| {
+        // Results without a value for the metric come last
+        return match (metric.value(r0), metric.value(r1)) {
+            (Some(v0), Some(v1)) => {
+                let ordering = v0.partial_cmp(&v1).unwrap_or(std::cmp::Ordering::Equal);
+                if metric.higher_is_better() { ordering.reverse() } else { ordering }
+            },
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal
+        };
+    });
+    return Ok(results.into_iter().enumerate()
+            .map(|(idx, (candidate, report))| SearchResult { rank: idx + 1, candidate, report })
+            .collect());
+}
+
+fn metric_name(metric: SearchMetric) -> &'static str {
+    return match metric {
+        SearchMetric::Rmse => "rmse",
+        SearchMetric::Mae => "mae",
+        SearchMetric::NdcgAtK => "ndcg_at_k",
+        SearchMetric::MapAtK => "map_at_k",
+        SearchMetric::RecallAtK => "recall_at_k",
+        SearchMetric::Auc => "auc"
+    };
+}
+
+fn format_metric(value: Option<f64>) -> String {
+    return match value {
+        Some(value) => format!("{:.5}", value),
+        None => String::from("-")
+    };
+}
+
+/// Render the ranked results as a plain-text table. Metrics that were not measured are shown as `-`.
+pub fn format_table(results: &[SearchResult]) -> String {
+    let mut table = format!("{:>4} | {:>8} | {:>10} | {:>14} | {:>6} | {:>8} | {:>8} | {:>8} | {:>8} | {:>8} | {:>8} | {:>8}\n",
+                            "rank", "features", "learn_rate", "regularization", "k", "rmse", "mae", "ndcg@k", "map@k", "recall@k", "auc", "coverage");
+    for result in results {
+        let (c, r) = (&result.candidate, &result.report);
+        table += &format!("{:>4} | {:>8} | {:>10} | {:>14} | {:>6} | {:>8} | {:>8} | {:>8.5} | {:>8.5} | {:>8.5} | {:>8.5} | {:>8.5}\n",
+                          result.rank, c.features, c.learn_rate, c.regularization_parameter, c.k,
+                          format_metric(r.rmse), format_metric(r.mae), r.ndcg_at_k, r.map_at_k, r.recall_at_k, r.auc, r.catalog_coverage);
+    }
+    return table;
+}
+
+/// Build the `training` section of recochan.json for the given result: the `base` section
+/// that every candidate was trained with, with the searched parameters of the result.
+pub fn training_snippet(base: &RecoChanSettingsTraining, result: &SearchResult) -> serde_json::Value {
+    let mut training = serde_json::to_value(base).unwrap();
+    training["features"] = json!(result.candidate.features);
+    training["learn_rate"] = json!(result.candidate.learn_rate);
+    training["regularization_parameter"] = json!(result.candidate.regularization_parameter);
+    training["k"] = json!(result.candidate.k);
+    return json!({ "training": training });
+}
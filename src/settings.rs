@@ -1,5 +1,8 @@
-use serde_derive::Deserialize;
-use config::{Config, File, ConfigError};
+use serde_derive::{Serialize, Deserialize};
+use config::{Config, File, ConfigError, Value};
+use crate::ratings::RatingScale;
+use crate::recommender::RecommendationEngineConf;
+use crate::scheduler::CronExpression;
 
 
 #[derive(Deserialize)]
@@ -9,6 +12,13 @@ pub struct RecoChanSettingsApi {
 }
 
 
+#[derive(Deserialize)]
+pub struct RecoChanSettingsInteraction {
+    pub value: String,
+    pub rating: f64
+}
+
+
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum RecoChanSettingsDataProvider {
@@ -18,9 +28,284 @@ pub enum RecoChanSettingsDataProvider {
         aid_name: String,
         uid_name: String,
         rating_name: String,
-        table_name: String
+        table_name: String,
+        interactions: Vec<RecoChanSettingsInteraction>
     },
-    TestCSV { path: String }
+    TestCSV { path: String, interactions: Vec<RecoChanSettingsInteraction> }
+}
+impl RecoChanSettingsDataProvider {
+    pub fn interactions(&self) -> &[RecoChanSettingsInteraction] {
+        return match self {
+            RecoChanSettingsDataProvider::SQL { interactions, .. } => interactions,
+            RecoChanSettingsDataProvider::TestCSV { interactions, .. } => interactions
+        };
+    }
+
+    fn validate(&self, scale: &RecoChanSettingsRatingScale) -> Result<(), ConfigError> {
+        for interaction in self.interactions() {
+            if !scale.scale().contains(interaction.rating) {
+                return Err(ConfigError::Message(format!("Invalid dataprovider configuration: rating of interaction {} has to be on the rating_scale", interaction.value)));
+            }
+        }
+        return Ok(());
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct RecoChanSettingsRatingScale {
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+    pub input_factor: f64,
+    pub input_offset: f64,
+    pub round_predictions: bool
+}
+impl RecoChanSettingsRatingScale {
+    pub fn scale(&self) -> RatingScale {
+        return RatingScale {
+            min: self.min,
+            max: self.max,
+            step: self.step,
+            input_factor: self.input_factor,
+            input_offset: self.input_offset
+        };
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |msg: &str| Err(ConfigError::Message(format!("Invalid rating_scale configuration: {}", msg)));
+        if !(self.min.is_finite() && self.max.is_finite()) { return error("min and max have to be finite numbers"); }
+        if !(self.min < self.max) { return error("min has to be smaller than max"); }
+        if !(self.step >= 0.0 && self.step <= self.max - self.min) { return error("step has to be in [0, max - min]"); }
+        if !(self.input_factor.is_finite() && self.input_factor != 0.0) { return error("input_factor has to be a finite number other than 0"); }
+        if !self.input_offset.is_finite() { return error("input_offset has to be a finite number"); }
+        return Ok(());
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct RecoChanSettingsSnapshot {
+    pub path: String,
+    pub serve_stale: bool
+}
+
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum RecoChanSettingsSplit {
+    Random { test_fraction: f64, seed: u64 },
+    LeaveNOut { n: usize, seed: u64 }
+}
+
+
+#[derive(Deserialize)]
+pub struct RecoChanSettingsEvaluation {
+    pub split: RecoChanSettingsSplit,
+    pub k: usize,
+    pub relevance_threshold: f64,
+    pub diversity: f64
+}
+impl RecoChanSettingsEvaluation {
+    fn validate(&self, scale: &RecoChanSettingsRatingScale) -> Result<(), ConfigError> {
+        let error = |msg: &str| Err(ConfigError::Message(format!("Invalid evaluation configuration: {}", msg)));
+        if !(self.relevance_threshold >= scale.min && self.relevance_threshold <= scale.max) { return error("relevance_threshold has to be in [rating_scale.min, rating_scale.max]"); }
+        if !(self.diversity >= 0.0 && self.diversity <= 1.0) { return error("diversity has to be in [0, 1]"); }
+        if self.k == 0 { return error("k has to be at least 1"); }
+        match self.split {
+            RecoChanSettingsSplit::Random { test_fraction, .. } if !(test_fraction >= 0.0 && test_fraction <= 1.0) => return error("split.test_fraction has to be in [0, 1]"),
+            RecoChanSettingsSplit::LeaveNOut { n: 0, .. } => return error("split.n has to be at least 1"),
+            _ => ()
+        }
+        return Ok(());
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub enum RecoChanSettingsSanitizer {
+    Noop,
+    Clamp
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub enum RecoChanSettingsInitialization {
+    Constant,
+    Uniform,
+    Normal
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub enum RecoChanSettingsTrainer {
+    FunkSvd,
+    Als,
+    BiasedSgd,
+    SvdPlusPlus,
+    ItemKnn,
+    Bpr
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub enum RecoChanSettingsNeighborhoodSimilarity {
+    AdjustedCosine,
+    Pearson
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct RecoChanSettingsTraining {
+    pub trainer: RecoChanSettingsTrainer,
+    pub features: usize,
+    pub feature_labels: Vec<String>,
+    pub learn_rate: f64,
+    pub threads: usize,
+    pub min_steps: usize,
+    pub max_steps: usize,
+    pub min_improvement: f64,
+    pub validation_fraction: f64,
+    pub curve_path: String,
+    pub regularization_parameter: f64,
+    pub bias_regularization_parameter: f64,
+    pub initial_approximation_value: f64,
+    pub initialization: RecoChanSettingsInitialization,
+    pub initialization_scale: f64,
+    pub shuffle: bool,
+    pub seed: u64,
+    pub online_steps: usize,
+    pub online_update_animes: bool,
+    pub positive_threshold: f64,
+    pub neighborhood_similarity: RecoChanSettingsNeighborhoodSimilarity,
+    pub neighborhood_size: usize,
+    pub neighborhood_shrinkage: f64,
+    pub k: f64,
+    pub sanitizer: RecoChanSettingsSanitizer
+}
+impl RecoChanSettingsTraining {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |msg: &str| Err(ConfigError::Message(format!("Invalid training configuration: {}", msg)));
+        if self.features == 0 { return error("features has to be at least 1"); }
+        if self.feature_labels.len() > self.features { return error("feature_labels must not have more labels than features"); }
+        if !(self.learn_rate > 0.0) { return error("learn_rate has to be positive"); }
+        if self.threads == 0 { return error("threads has to be at least 1"); }
+        if self.max_steps == 0 { return error("max_steps has to be at least 1"); }
+        if self.min_steps > self.max_steps { return error("min_steps must not be larger than max_steps"); }
+        if !(self.min_improvement >= 0.0) { return error("min_improvement must not be negative"); }
+        if !(self.validation_fraction >= 0.0 && self.validation_fraction < 1.0) { return error("validation_fraction has to be in [0, 1)"); }
+        if !(self.regularization_parameter >= 0.0) { return error("regularization_parameter must not be negative"); }
+        if !(self.bias_regularization_parameter >= 0.0) { return error("bias_regularization_parameter must not be negative"); }
+        if !self.initial_approximation_value.is_finite() { return error("initial_approximation_value has to be a finite number"); }
+        if !(self.initialization_scale >= 0.0) { return error("initialization_scale must not be negative"); }
+        if self.neighborhood_size == 0 { return error("neighborhood_size has to be at least 1"); }
+        if !(self.neighborhood_shrinkage >= 0.0) { return error("neighborhood_shrinkage must not be negative"); }
+        if !(self.k >= 0.0) { return error("k must not be negative"); }
+        return Ok(());
+    }
+}
+
+
+#[derive(Deserialize)]
+pub enum RecoChanSettingsSimilarityMetric {
+    Euclidean,
+    Cosine,
+    DotProduct
+}
+
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum RecoChanSettingsNeighborIndex {
+    KdTree,
+    BruteForce,
+    Hnsw { m: usize, ef_construction: usize, ef_search: usize }
+}
+
+
+#[derive(Deserialize)]
+pub struct RecoChanSettingsSimilarity {
+    pub metric: RecoChanSettingsSimilarityMetric,
+    pub index: RecoChanSettingsNeighborIndex
+}
+
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum RecoChanSettingsSearchStrategy {
+    Grid,
+    Random { samples: usize, seed: u64 }
+}
+
+
+#[derive(Deserialize)]
+pub enum RecoChanSettingsSearchMetric {
+    Rmse,
+    Mae,
+    NdcgAtK,
+    MapAtK,
+    RecallAtK,
+    Auc
+}
+
+
+#[derive(Deserialize)]
+pub struct RecoChanSettingsSearch {
+    pub strategy: RecoChanSettingsSearchStrategy,
+    pub rank_by: RecoChanSettingsSearchMetric,
+    pub threads: usize,
+    pub features: Vec<usize>,
+    pub learn_rate: Vec<f64>,
+    pub regularization_parameter: Vec<f64>,
+    pub k: Vec<f64>
+}
+impl RecoChanSettingsSearch {
+    /// Every candidate value has to pass the checks of `RecoChanSettingsTraining::validate`.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let error = |msg: &str| Err(ConfigError::Message(format!("Invalid search configuration: {}", msg)));
+        if let RecoChanSettingsSearchStrategy::Random { samples: 0, .. } = self.strategy { return error("samples has to be at least 1"); }
+        if self.threads == 0 { return error("threads has to be at least 1"); }
+        if self.features.is_empty() || self.learn_rate.is_empty() || self.regularization_parameter.is_empty() || self.k.is_empty() {
+            return error("features, learn_rate, regularization_parameter and k need at least one value each");
+        }
+        if self.features.iter().any(|&features| features == 0) { return error("features have to be at least 1"); }
+        if self.learn_rate.iter().any(|&learn_rate| !(learn_rate > 0.0)) { return error("learn_rates have to be positive"); }
+        if self.regularization_parameter.iter().any(|&parameter| !(parameter >= 0.0)) { return error("regularization_parameters must not be negative"); }
+        if self.k.iter().any(|&k| !(k >= 0.0)) { return error("k must not be negative"); }
+        return Ok(());
+    }
+}
+
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum RecoChanSettingsFallbackStrategy {
+    Popular,
+    Trending { window_secs: u64 },
+    Curated { animes: Vec<u64> }
+}
+
+
+#[derive(Deserialize)]
+pub struct RecoChanSettingsFallback {
+    pub strategies: Vec<RecoChanSettingsFallbackStrategy>,
+    pub count: usize
+}
+
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum RecoChanSettingsSchedule {
+    Interval,
+    Cron { expression: String }
+}
+
+
+#[derive(Deserialize)]
+pub struct RecoChanSettingsRetry {
+    pub initial_backoff_sec: u64,
+    pub max_backoff_sec: u64,
+    pub max_attempts: usize
 }
 
 
@@ -28,6 +313,15 @@ pub enum RecoChanSettingsDataProvider {
 pub struct RecoChanSettings {
     pub api: RecoChanSettingsApi,
     pub dataprovider: RecoChanSettingsDataProvider,
+    pub rating_scale: RecoChanSettingsRatingScale,
+    pub training: RecoChanSettingsTraining,
+    pub similarity: RecoChanSettingsSimilarity,
+    pub snapshot: RecoChanSettingsSnapshot,
+    pub evaluation: RecoChanSettingsEvaluation,
+    pub search: RecoChanSettingsSearch,
+    pub fallback: RecoChanSettingsFallback,
+    pub retry: RecoChanSettingsRetry,
+    pub schedule: RecoChanSettingsSchedule,
     pub retrain_every_sec: u64
 }
 
@@ -39,9 +333,107 @@ impl RecoChanSettings {
         // Add defaults
         settings.set_default("api.bind", "127.0.0.1").unwrap();
         settings.set_default("api.port", 1337).unwrap();
+        settings.set_default("dataprovider.interactions", Vec::<Value>::new()).unwrap();
+        let training_defaults = RecommendationEngineConf::default();
+        settings.set_default("rating_scale.min", training_defaults.rating_scale.min).unwrap();
+        settings.set_default("rating_scale.max", training_defaults.rating_scale.max).unwrap();
+        settings.set_default("rating_scale.step", training_defaults.rating_scale.step).unwrap();
+        settings.set_default("rating_scale.input_factor", training_defaults.rating_scale.input_factor).unwrap();
+        settings.set_default("rating_scale.input_offset", training_defaults.rating_scale.input_offset).unwrap();
+        settings.set_default("rating_scale.round_predictions", training_defaults.round_predictions).unwrap();
+        settings.set_default("training.trainer", "FunkSvd").unwrap();
+        settings.set_default("training.features", training_defaults.features as i64).unwrap();
+        settings.set_default("training.feature_labels", Vec::<String>::new()).unwrap();
+        settings.set_default("training.learn_rate", training_defaults.learn_rate).unwrap();
+        settings.set_default("training.threads", training_defaults.threads as i64).unwrap();
+        settings.set_default("training.min_steps", training_defaults.min_steps as i64).unwrap();
+        settings.set_default("training.max_steps", training_defaults.max_steps as i64).unwrap();
+        settings.set_default("training.min_improvement", training_defaults.min_improvement).unwrap();
+        settings.set_default("training.validation_fraction", training_defaults.validation_fraction).unwrap();
+        settings.set_default("training.curve_path", "").unwrap();
+        settings.set_default("training.regularization_parameter", training_defaults.regularization_parameter).unwrap();
+        settings.set_default("training.bias_regularization_parameter", training_defaults.bias_regularization_parameter).unwrap();
+        settings.set_default("training.initial_approximation_value", training_defaults.initial_approximation_value).unwrap();
+        settings.set_default("training.initialization", "Constant").unwrap();
+        settings.set_default("training.initialization_scale", training_defaults.initialization_scale).unwrap();
+        settings.set_default("training.shuffle", training_defaults.shuffle).unwrap();
+        settings.set_default("training.seed", training_defaults.seed as i64).unwrap();
+        settings.set_default("training.online_steps", training_defaults.online_steps as i64).unwrap();
+        settings.set_default("training.online_update_animes", training_defaults.online_update_animes).unwrap();
+        settings.set_default("training.neighborhood_similarity", "AdjustedCosine").unwrap();
+        settings.set_default("training.neighborhood_size", training_defaults.neighborhood_size as i64).unwrap();
+        settings.set_default("training.neighborhood_shrinkage", training_defaults.neighborhood_shrinkage).unwrap();
+        settings.set_default("training.k", training_defaults.k).unwrap();
+        settings.set_default("training.sanitizer", "Clamp").unwrap();
+        settings.set_default("similarity.metric", "Euclidean").unwrap();
+        settings.set_default("similarity.index.type", "KdTree").unwrap();
+        settings.set_default("similarity.index.m", 16).unwrap();
+        settings.set_default("similarity.index.ef_construction", 200).unwrap();
+        settings.set_default("similarity.index.ef_search", 64).unwrap();
+        settings.set_default("snapshot.path", "").unwrap();
+        settings.set_default("snapshot.serve_stale", true).unwrap();
+        settings.set_default("evaluation.split.type", "Random").unwrap();
+        settings.set_default("evaluation.split.test_fraction", 0.2).unwrap();
+        settings.set_default("evaluation.split.n", 1).unwrap();
+        settings.set_default("evaluation.split.seed", 0).unwrap();
+        settings.set_default("evaluation.k", 10).unwrap();
+        settings.set_default("evaluation.diversity", 0.0).unwrap();
+        settings.set_default("search.strategy.type", "Grid").unwrap();
+        settings.set_default("search.strategy.samples", 20).unwrap();
+        settings.set_default("search.strategy.seed", 0).unwrap();
+        settings.set_default("search.rank_by", "Rmse").unwrap();
+        settings.set_default("search.threads", 4).unwrap();
+        settings.set_default("search.features", vec![10, 25, 40]).unwrap();
+        settings.set_default("search.learn_rate", vec![0.005, 0.01, 0.02]).unwrap();
+        settings.set_default("search.regularization_parameter", vec![0.01, 0.02, 0.05]).unwrap();
+        settings.set_default("search.k", vec![10.0, 25.0, 50.0]).unwrap();
+        settings.set_default("fallback.strategies", Vec::<Value>::new()).unwrap();
+        settings.set_default("fallback.count", training_defaults.fallback_count as i64).unwrap();
+        settings.set_default("retry.initial_backoff_sec", 30).unwrap();
+        settings.set_default("retry.max_backoff_sec", 60*60).unwrap();
+        settings.set_default("retry.max_attempts", 5).unwrap();
+        settings.set_default("schedule.type", "Interval").unwrap();
         settings.set_default("retrain_every_sec", 24*60*60).unwrap();
 
         settings.merge(File::with_name(filename))?;
-        return settings.try_into();
+        // Thresholds of what counts as a good rating depend on the configured rating scale
+        let (scale_min, scale_max) = (settings.get_float("rating_scale.min")?, settings.get_float("rating_scale.max")?);
+        let good_rating = RatingScale { min: scale_min, max: scale_max, ..RatingScale::default() }.good_rating();
+        settings.set_default("training.positive_threshold", good_rating).unwrap();
+        settings.set_default("evaluation.relevance_threshold", good_rating).unwrap();
+        let settings: Self = settings.try_into()?;
+        settings.rating_scale.validate()?;
+        settings.dataprovider.validate(&settings.rating_scale)?;
+        settings.training.validate()?;
+        let (scale_min, scale_max) = (settings.rating_scale.min, settings.rating_scale.max);
+        if !(settings.training.positive_threshold >= scale_min && settings.training.positive_threshold <= scale_max) {
+            return Err(ConfigError::Message("Invalid training configuration: positive_threshold has to be in [rating_scale.min, rating_scale.max]".to_owned()));
+        }
+        settings.evaluation.validate(&settings.rating_scale)?;
+        settings.search.validate()?;
+        match (&settings.training.trainer, &settings.search.rank_by) {
+            (RecoChanSettingsTrainer::Bpr, RecoChanSettingsSearchMetric::Rmse) | (RecoChanSettingsTrainer::Bpr, RecoChanSettingsSearchMetric::Mae) => {
+                return Err(ConfigError::Message("Invalid search configuration: Bpr models predict no ratings, so they can't be ranked by Rmse or Mae".to_owned()));
+            },
+            _ => ()
+        }
+        if settings.retry.initial_backoff_sec == 0 || settings.retry.initial_backoff_sec > settings.retry.max_backoff_sec {
+            return Err(ConfigError::Message("Invalid retry configuration: initial_backoff_sec has to be in [1, max_backoff_sec]".to_owned()));
+        }
+        if settings.retry.max_attempts == 0 {
+            return Err(ConfigError::Message("Invalid retry configuration: max_attempts has to be at least 1".to_owned()));
+        }
+        match &settings.schedule {
+            RecoChanSettingsSchedule::Interval if settings.retrain_every_sec == 0 => {
+                return Err(ConfigError::Message("Invalid schedule configuration: retrain_every_sec has to be at least 1".to_owned()));
+            },
+            RecoChanSettingsSchedule::Cron { expression } => {
+                if let Err(e) = expression.parse::<CronExpression>() {
+                    return Err(ConfigError::Message(format!("Invalid schedule configuration: {}", e)));
+                }
+            },
+            _ => {}
+        }
+        return Ok(settings);
     }
 }
\ No newline at end of file
@@ -0,0 +1,137 @@
+use nalgebra as na;
+use serde_derive::{Serialize, Deserialize};
+use std::{fmt, io, fs, path::Path, time::{SystemTime, UNIX_EPOCH}};
+use crate::ratings::{Id, Index, RatingValue};
+use crate::recommender::TrainerKind;
+
+/// Version of the snapshot file format. This has to be increased whenever
+/// the layout of `EngineSnapshot` changes, so that old snapshots are
+/// rejected instead of being misinterpreted.
+pub const SNAPSHOT_VERSION: u32 = 7;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+    Inconsistent
+}
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "Failed to access snapshot file: {}", e),
+            SnapshotError::Format(e) => write!(f, "Failed to (de)serialize snapshot: {}", e),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "Snapshot has version {}, but only version {} is supported", v, SNAPSHOT_VERSION),
+            SnapshotError::Inconsistent => write!(f, "Snapshot dimensions do not match each other, or it was trained with a different trainer")
+        }
+    }
+}
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self { SnapshotError::Io(e) }
+}
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self { SnapshotError::Format(e) }
+}
+
+
+/// Column-major dense matrix in a serializable form.
+#[derive(Serialize, Deserialize)]
+pub struct MatrixSnapshot {
+    pub nrows: usize,
+    pub ncols: usize,
+    pub data: Vec<RatingValue>
+}
+impl MatrixSnapshot {
+    pub fn from_matrix(matrix: &na::DMatrix<RatingValue>) -> Self {
+        return Self { nrows: matrix.nrows(), ncols: matrix.ncols(), data: matrix.as_slice().to_vec() };
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        return self.data.len() == self.nrows * self.ncols;
+    }
+
+    pub fn into_matrix(self) -> na::DMatrix<RatingValue> {
+        return na::DMatrix::from_vec(self.nrows, self.ncols, self.data);
+    }
+}
+
+
+/// Just the version of a snapshot, all other fields are ignored.
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32
+}
+
+
+/// Serializable representation of a trained model. This contains everything
+/// that is required to answer predictions, except for the spatial indices,
+/// which are rebuilt from the feature matrices after loading.
+#[derive(Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub version: u32,
+    /// Unix timestamp (seconds) of when the snapshot was created.
+    pub created_at: u64,
+    /// Trainer of the model, which decides whether it predicts ratings or ranking scores.
+    pub trainer: TrainerKind,
+
+    pub global_rating_avg: RatingValue,
+    pub global_avg_offset: RatingValue,
+    pub anime_rating_cnt: Vec<usize>,
+    pub anime_rating_avg: Vec<RatingValue>,
+    pub user_rating_cnt: Vec<usize>,
+    pub user_avg_offset: Vec<RatingValue>,
+    pub global_bias: RatingValue,
+    pub anime_bias: Vec<RatingValue>,
+    pub user_bias: Vec<RatingValue>,
+
+    pub anime_features: MatrixSnapshot,
+    pub user_features: MatrixSnapshot,
+    /// Only present for SVD++ models.
+    pub implicit_anime_features: Option<MatrixSnapshot>,
+    pub implicit_user_features: Option<MatrixSnapshot>,
+    /// Only present for item-kNN models: (anime, similarity) of the most similar animes per anime.
+    pub item_neighbors: Option<Vec<Vec<(Index, RatingValue)>>>,
+
+    /// Anime ids in row order of `anime_features`.
+    pub anime_ids: Vec<Id>,
+    /// User ids in column order of `user_features`.
+    pub user_ids: Vec<Id>,
+    /// Per user: (anime, rating) of every anime the user rated.
+    pub rated: Vec<Vec<(Index, RatingValue)>>,
+
+    pub approximation_error: RatingValue
+}
+impl EngineSnapshot {
+    pub fn now() -> u64 {
+        return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    }
+
+    /// Age of this snapshot in seconds.
+    pub fn age(&self) -> u64 {
+        return Self::now().saturating_sub(self.created_at);
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        let data = fs::read(path)?;
+        // Check the version first, since the rest of an old snapshot may have a different layout
+        let header: SnapshotHeader = serde_json::from_slice(&data)?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(header.version));
+        }
+        return Ok(serde_json::from_slice(&data)?);
+    }
+
+    /// Write the snapshot to the given path. The snapshot is first written to a
+    /// temporary file next to the target, which is then renamed. This way, a crash
+    /// during writing never leaves a half-written snapshot behind.
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = io::BufWriter::new(fs::File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, self)?;
+            io::Write::flush(&mut writer)?;
+        }
+        fs::rename(&tmp_path, path)?;
+        return Ok(());
+    }
+}